@@ -0,0 +1,228 @@
+use age::armor::ArmoredReader;
+use age::secrecy::SecretString;
+use age::{DecryptError, Decryptor, Identity, IdentityFile};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::bitwarden::confirm::ask_password;
+use crate::config::Config;
+
+/// Extension of the encrypted key files in a store directory
+const EXTENSION: &str = "age";
+
+/// Settings of the `age` backend
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgeConfig {
+    /// Encrypted file holding all the keys, or directory with one `<secret id>.age` file per key
+    #[serde(default)]
+    pub path: Option<String>,
+    /// age identity file decrypting the store, the passphrase is asked for without it
+    #[serde(default)]
+    pub identity_file: Option<String>,
+}
+
+/// What decrypts the store
+pub enum AgeKey {
+    /// Passphrase the store was encrypted with (`age --passphrase`)
+    Passphrase(Zeroizing<String>),
+    /// Content of an identity file, e.g. from `age-keygen`
+    Identities(Zeroizing<String>),
+}
+
+/// Entry of an encrypted key file: the key text, or the key with a note
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoreEntry {
+    Key(Zeroizing<String>),
+    Detailed {
+        key: Zeroizing<String>,
+        #[serde(default)]
+        note: String,
+    },
+}
+
+/// Check that a secret ID names a key of the store, and not a path out of it
+pub fn check_name(reference: &str) -> Result<()> {
+    let valid = !reference.is_empty()
+        && !reference.starts_with('.')
+        && reference
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        bail!(
+            "Invalid age key name '{}', expected letters, digits, '.', '_' or '-' \
+            and no leading '.'",
+            reference
+        );
+    }
+    Ok(())
+}
+
+/// Check the `age` section of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    if config.age.path.is_none() {
+        bail!("age.path must be set to the encrypted key file or directory");
+    }
+    Ok(())
+}
+
+/// Path of the configured store, for diagnostics
+pub fn store_path(config: &Config) -> String {
+    config.age.path.clone().unwrap_or_default()
+}
+
+/// Read the identity file, or ask for the passphrase, then check that the store decrypts
+pub async fn connect(config: &Config) -> Result<AgeClient> {
+    let age = &config.age;
+    let path = PathBuf::from(age.path.as_ref().context("age.path is not set")?);
+    let key = match &age.identity_file {
+        Some(identity_file) => AgeKey::Identities(Zeroizing::new(
+            std::fs::read_to_string(identity_file)
+                .with_context(|| format!("Failed to read age identity file {}", identity_file))?,
+        )),
+        None => AgeKey::Passphrase(
+            ask_password(&format!("Passphrase of age store {}: ", path.display())).await?,
+        ),
+    };
+    AgeClient::open(path, key).await
+}
+
+/// Reader of keys stored in age-encrypted files.
+///
+/// The store is either a single file holding a YAML map of key names to keys, or a
+/// directory with one encrypted key per file. Files are decrypted again for every fetch,
+/// only the requested key is kept.
+#[derive(Clone)]
+pub struct AgeClient {
+    path: PathBuf,
+    key: Arc<AgeKey>,
+}
+
+impl AgeClient {
+    pub async fn open(path: PathBuf, key: AgeKey) -> Result<Self> {
+        let client = Self {
+            path,
+            key: Arc::new(key),
+        };
+
+        // Fail at startup on a wrong passphrase or identity rather than on the first signature
+        let (path, key) = (client.path.clone(), client.key.clone());
+        let keys = tokio::task::spawn_blocking(move || -> Result<usize> {
+            if path.is_dir() {
+                let files = store_files(&path)?;
+                if let Some(first) = files.first() {
+                    decrypt_file(first, &key)?;
+                }
+                Ok(files.len())
+            } else {
+                Ok(read_store_file(&path, &key)?.len())
+            }
+        })
+        .await
+        .context("age: Store reader failed")??;
+        info!("Opened age store {} ({} keys)", client.path.display(), keys);
+        Ok(client)
+    }
+}
+
+/// Encrypted key files of a store directory, sorted by name
+fn store_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("age: Failed to read store directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Decrypt an age file, armored or binary
+fn decrypt_file(path: &Path, key: &AgeKey) -> Result<Zeroizing<Vec<u8>>> {
+    let ciphertext =
+        std::fs::read(path).with_context(|| format!("age: Failed to read {}", path.display()))?;
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext.as_slice()))
+        .with_context(|| format!("age: {} is not an age file", path.display()))?;
+    let reader = match key {
+        AgeKey::Passphrase(passphrase) => {
+            let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+            decryptor.decrypt(std::iter::once(&identity as &dyn Identity))
+        }
+        AgeKey::Identities(text) => {
+            let identities = IdentityFile::from_buffer(text.as_bytes())
+                .context("age: Invalid identity file")?
+                .into_identities()
+                .context("age: Invalid identity file")?;
+            decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))
+        }
+    };
+    let mut reader = reader.map_err(|e| match e {
+        DecryptError::NoMatchingKeys | DecryptError::DecryptionFailed => {
+            anyhow!("age: Wrong passphrase or identity for {}", path.display())
+        }
+        e => anyhow!("age: Failed to decrypt {}.\nError: {}", path.display(), e),
+    })?;
+    let mut plaintext = Zeroizing::new(Vec::new());
+    reader
+        .read_to_end(&mut plaintext)
+        .with_context(|| format!("age: Failed to decrypt {}", path.display()))?;
+    Ok(plaintext)
+}
+
+/// Decrypt a store file and parse its map of key names to keys
+fn read_store_file(path: &Path, key: &AgeKey) -> Result<HashMap<String, StoreEntry>> {
+    let plaintext = decrypt_file(path, key)?;
+    serde_yaml::from_slice(&plaintext).with_context(|| {
+        format!(
+            "age: {} should hold a map of key names to keys",
+            path.display()
+        )
+    })
+}
+
+/// Text of a decrypted key file
+fn key_text(plaintext: Zeroizing<Vec<u8>>, path: &Path) -> Result<Zeroizing<String>> {
+    let text = std::str::from_utf8(&plaintext)
+        .with_context(|| format!("age: {} is not a text key", path.display()))?;
+    Ok(Zeroizing::new(text.to_string()))
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for AgeClient {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let name = id.to_string();
+        check_name(&name)?;
+        let (path, key) = (self.path.clone(), self.key.clone());
+        tokio::task::spawn_blocking(move || {
+            let (value, note) = if path.is_dir() {
+                let file = path.join(format!("{}.{}", name, EXTENSION));
+                if !file.exists() {
+                    bail!("age: Key '{}' not found in {}", name, path.display());
+                }
+                (key_text(decrypt_file(&file, &key)?, &file)?, String::new())
+            } else {
+                match read_store_file(&path, &key)?.remove(&name) {
+                    Some(StoreEntry::Key(value)) => (value, String::new()),
+                    Some(StoreEntry::Detailed { key, note }) => (key, note),
+                    None => bail!("age: Key '{}' not found in {}", name, path.display()),
+                }
+            };
+            debug!("Read key '{}' from age store", name);
+            Ok(SecretData {
+                name,
+                value,
+                note,
+                project: None,
+            })
+        })
+        .await
+        .context("age: Store reader failed")?
+    }
+}
@@ -0,0 +1,505 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bitwarden::{
+    auth::login::AccessTokenLoginRequest,
+    secrets_manager::{
+        projects::{ProjectGetRequest, ProjectsListRequest},
+        secrets::{
+            SecretGetRequest, SecretIdentifiersByProjectRequest, SecretIdentifiersRequest,
+            SecretsGetRequest,
+        },
+    },
+    Client, ClientSettings, DeviceType,
+};
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::bitwarden::keyring::parse_secret_value;
+use crate::config::Config;
+use crate::file_manager::{get_state_dir, remove_file};
+
+// Real implementation wrapper - needs to be Clone
+#[derive(Clone)]
+pub struct BitwardenClientWrapper {
+    client: Arc<Client>,
+    /// Project names already resolved, keyed by project ID
+    project_names: Arc<Mutex<HashMap<Uuid, String>>>,
+    /// Projects whose secrets holding SSH keys are served too
+    project_ids: Arc<Vec<Uuid>>,
+    /// Organization listed to resolve secret names
+    organization_id: Option<Uuid>,
+}
+
+/// Secret of the organization, to resolve references by name
+pub struct SecretName {
+    pub id: Uuid,
+    pub key: String,
+    /// Name of the project of the secret, if any
+    pub project: Option<String>,
+}
+
+impl BitwardenClientWrapper {
+    /// Name of a project, the ID is used if the name cannot be read
+    async fn get_project_name(&self, id: Uuid) -> String {
+        if let Some(name) = self.project_names.lock().unwrap().get(&id) {
+            return name.clone();
+        }
+        let name = match self.client.projects().get(&ProjectGetRequest { id }).await {
+            Ok(project) => project.name,
+            Err(e) => {
+                warn!(
+                    "Bitwarden SDK: Failed to read name of project '{}': {}",
+                    id, e
+                );
+                id.to_string()
+            }
+        };
+        self.project_names.lock().unwrap().insert(id, name.clone());
+        name
+    }
+
+    /// IDs of the secrets of `project_id`
+    async fn list_project(&self, project_id: Uuid) -> Result<Vec<Uuid>> {
+        let response = self
+            .client
+            .secrets()
+            .list_by_project(&SecretIdentifiersByProjectRequest { project_id })
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Bitwarden SDK: Failed to list secrets of project '{}'.\nError: {}",
+                    project_id,
+                    e
+                )
+            })?;
+        Ok(response.data.into_iter().map(|secret| secret.id).collect())
+    }
+
+    /// Secrets of the organization, with their project names when `with_projects`
+    async fn list_secrets(
+        &self,
+        organization_id: Uuid,
+        with_projects: bool,
+    ) -> Result<Vec<SecretName>> {
+        let response = self
+            .client
+            .secrets()
+            .list(&SecretIdentifiersRequest { organization_id })
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Bitwarden SDK: Failed to list secrets of organization '{}'.\nError: {}",
+                    organization_id,
+                    e
+                )
+            })?;
+        let mut secrets: Vec<SecretName> = response
+            .data
+            .into_iter()
+            .map(|secret| SecretName {
+                id: secret.id,
+                key: secret.key,
+                project: None,
+            })
+            .collect();
+        if !with_projects {
+            return Ok(secrets);
+        }
+
+        let projects = self
+            .client
+            .projects()
+            .list(&ProjectsListRequest { organization_id })
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Bitwarden SDK: Failed to list projects of organization '{}'.\nError: {}",
+                    organization_id,
+                    e
+                )
+            })?;
+        for project in projects.data {
+            self.project_names
+                .lock()
+                .unwrap()
+                .insert(project.id, project.name.clone());
+            for id in self.list_project(project.id).await? {
+                if let Some(secret) = secrets.iter_mut().find(|secret| secret.id == id) {
+                    secret.project = Some(project.name.clone());
+                }
+            }
+        }
+        Ok(secrets)
+    }
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for BitwardenClientWrapper {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let id = parse_secret_id(id.as_str())?;
+        let request = SecretGetRequest { id };
+        let response = self.client.secrets().get(&request).await.map_err(|e| {
+            let error_msg = e.to_string();
+            if error_msg.contains("404") || error_msg.to_lowercase().contains("not found") {
+                anyhow!(
+                    "Bitwarden SDK: Secret '{}' not found (404). \
+                    Please verify the secret ID exists and you have access to it",
+                    id
+                )
+            } else {
+                anyhow!(
+                    "Bitwarden SDK: Failed to fetch secret '{}'.\nError: {}",
+                    id,
+                    e
+                )
+            }
+        })?;
+        let project = match response.project_id {
+            Some(project_id) => Some(self.get_project_name(project_id).await),
+            None => None,
+        };
+        Ok(SecretData {
+            name: response.key,
+            value: Zeroizing::new(response.value),
+            note: response.note,
+            project,
+        })
+    }
+
+    /// Secrets of the configured projects whose value parses as SSH keys
+    async fn discover_secrets(&self) -> Result<Vec<SecretId>> {
+        let mut ids = Vec::new();
+        for project_id in self.project_ids.iter() {
+            for id in self.list_project(*project_id).await? {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let response = self
+            .client
+            .secrets()
+            .get_by_ids(SecretsGetRequest { ids })
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Bitwarden SDK: Failed to fetch project secrets.\nError: {}",
+                    e
+                )
+            })?;
+        let total = response.data.len();
+        let mut keys = Vec::new();
+        for secret in response.data {
+            let value = Zeroizing::new(secret.value);
+            match parse_secret_value(&value) {
+                Ok(_) => keys.push(SecretId::from(secret.id)),
+                Err(e) => debug!(
+                    "Skipping secret '{}' ({}) of a project, not an SSH key: {}",
+                    secret.key, secret.id, e
+                ),
+            }
+        }
+        info!(
+            "Found {} SSH key(s) in {} secret(s) of {} project(s)",
+            keys.len(),
+            total,
+            self.project_ids.len()
+        );
+        Ok(keys)
+    }
+
+    /// IDs of the secrets named by `references`, listing the secrets of the organization
+    async fn resolve_references(
+        &self,
+        references: &[String],
+    ) -> Result<HashMap<String, Vec<SecretId>>> {
+        let organization_id = self.organization_id.ok_or_else(|| {
+            anyhow!(
+                "Secret references {} are not UUIDs, set bw_organization_id to resolve them by name",
+                references.join(", ")
+            )
+        })?;
+        let with_projects = references.iter().any(|reference| reference.contains('/'));
+        let secrets = self.list_secrets(organization_id, with_projects).await?;
+
+        let mut resolved = HashMap::new();
+        for reference in references {
+            let ids = resolve_reference(reference, &secrets)?;
+            info!(
+                "Secret reference '{}' resolved to {} secret(s)",
+                reference,
+                ids.len()
+            );
+            resolved.insert(
+                reference.clone(),
+                ids.into_iter().map(SecretId::from).collect(),
+            );
+        }
+        Ok(resolved)
+    }
+}
+
+/// Whether `reference` names secrets by key instead of by UUID, resolved when connecting
+pub fn is_secret_name(reference: &str) -> bool {
+    Uuid::parse_str(reference).is_err()
+}
+
+/// IDs of the secrets `reference` designates: a UUID, or `<key>` or `<project>/<key>`
+/// where both may be glob patterns with `*` and `?`. A name without wildcards must
+/// match exactly one secret.
+pub fn resolve_reference(reference: &str, secrets: &[SecretName]) -> Result<Vec<Uuid>> {
+    if let Ok(id) = Uuid::parse_str(reference) {
+        return Ok(vec![id]);
+    }
+    let (project, key) = match reference.split_once('/') {
+        Some((project, key)) => (Some(project), key),
+        None => (None, reference),
+    };
+    let ids: Vec<Uuid> = secrets
+        .iter()
+        .filter(|secret| glob_match(key, &secret.key))
+        .filter(|secret| match project {
+            Some(project) => secret
+                .project
+                .as_deref()
+                .is_some_and(|name| glob_match(project, name)),
+            None => true,
+        })
+        .map(|secret| secret.id)
+        .collect();
+
+    if reference.contains(['*', '?']) {
+        if ids.is_empty() {
+            warn!("Secret pattern '{}' matches no secret", reference);
+        }
+        return Ok(ids);
+    }
+    match ids.len() {
+        0 => bail!("No secret named '{}' in the organization", reference),
+        1 => Ok(ids),
+        count => bail!(
+            "Secret name '{}' is ambiguous, it matches {} secrets: {}. \
+            Reference it by project (`<project>/<key>`) or by UUID",
+            reference,
+            count,
+            ids.iter()
+                .map(Uuid::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any characters and `?` one character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and of the text it was matched against
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// API URL of the configured server, Bitwarden cloud by default
+pub fn api_url(config: &Config) -> String {
+    config
+        .bw_server_endpoint
+        .as_ref()
+        .map(|host| {
+            if host == "bitwarden.com" || host == "bitwarden.eu" {
+                // Cloud instance - use subdomain pattern
+                format!("https://api.{}", host)
+            } else {
+                // Self-hosted - use path pattern
+                format!("https://{}/api", host)
+            }
+        })
+        .unwrap_or_else(|| "https://api.bitwarden.com".to_string())
+}
+
+/// Identity URL of the configured server, Bitwarden cloud by default
+pub fn identity_url(config: &Config) -> String {
+    config
+        .bw_server_endpoint
+        .as_ref()
+        .map(|host| {
+            if host == "bitwarden.com" || host == "bitwarden.eu" {
+                // Cloud instance - use subdomain pattern
+                format!("https://identity.{}", host)
+            } else {
+                // Self-hosted - use path pattern
+                format!("https://{}/identity", host)
+            }
+        })
+        .unwrap_or_else(|| "https://identity.bitwarden.com".to_string())
+}
+
+/// Secrets are referenced by their UUID, shown under the secret name in the web app
+fn parse_secret_id(id: &str) -> Result<Uuid> {
+    Uuid::parse_str(id)
+        .with_context(|| format!("Invalid Bitwarden secret ID, expected a UUID: {}", id))
+}
+
+/// Check the Bitwarden settings of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    if config.bws_access_token.is_empty() {
+        bail!(format!(
+            "Config file not found at {} and BWS_ACCESS_TOKEN environment variable is not set",
+            Config::get_config_path()?.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Client settings of the configured server
+fn client_settings(config: &Config) -> ClientSettings {
+    ClientSettings {
+        identity_url: identity_url(config),
+        api_url: api_url(config),
+        user_agent: format!("vault-conductor/{}", env!("CARGO_PKG_VERSION")),
+        device_type: DeviceType::SDK,
+        bitwarden_client_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        bitwarden_package_type: None,
+        device_identifier: None,
+    }
+}
+
+/// Log in with the access token, reusing and updating the authentication state saved
+/// in `state_file` if set
+async fn login(config: &Config, state_file: Option<PathBuf>) -> Result<Client> {
+    let client = Client::new(Some(client_settings(config)));
+    // The request owns a copy of the token, wiped once logged in
+    let mut request = AccessTokenLoginRequest {
+        access_token: config.bws_access_token.to_string(),
+        state_file,
+    };
+    let result = client.auth().login_access_token(&request).await;
+    request.access_token.zeroize();
+    result.map_err(|e| {
+        anyhow!(
+            "Bitwarden SDK: Authentication failed.\nPlease check your access token. \
+                The token may be invalid, expired, or from an incompatible SDK version.\nError: {}",
+            e
+        )
+    })?;
+    Ok(client)
+}
+
+/// File the authentication state of the configured access token and server is saved
+/// to in `state_dir`, each token gets a file of its own
+pub fn auth_state_file(state_dir: &Path, config: &Config) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(api_url(config).as_bytes());
+    hasher.update(b"\n");
+    hasher.update(config.bws_access_token.as_bytes());
+    let digest: String = hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    state_dir.join(format!("bitwarden-auth-{}.json", digest))
+}
+
+/// Create `state_dir` readable by the owner only, or restrict it if it exists
+pub fn prepare_state_dir(state_dir: &Path) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(state_dir)
+        .with_context(|| format!("Failed to create state directory {}", state_dir.display()))?;
+    fs::set_permissions(state_dir, fs::Permissions::from_mode(0o700)).with_context(|| {
+        format!(
+            "Failed to set permissions of state directory {}",
+            state_dir.display()
+        )
+    })
+}
+
+/// Log in reusing the saved authentication state, and log in again from scratch if
+/// the state is unusable, e.g. corrupted or written by another SDK version
+async fn login_with_state(config: &Config) -> Result<Client> {
+    let state_dir = get_state_dir()?;
+    prepare_state_dir(&state_dir)?;
+    let state_file = auth_state_file(&state_dir, config);
+
+    let client = match login(config, Some(state_file.clone())).await {
+        Ok(client) => client,
+        Err(e) if state_file.exists() => {
+            warn!(
+                "Failed to log in with the authentication state saved in {}, \
+                logging in again: {:#}",
+                state_file.display(),
+                e
+            );
+            remove_file(&state_file, "authentication state")?;
+            login(config, Some(state_file.clone())).await?
+        }
+        Err(e) => return Err(e),
+    };
+    if state_file.exists() {
+        fs::set_permissions(&state_file, fs::Permissions::from_mode(0o600)).with_context(|| {
+            format!(
+                "Failed to set permissions of authentication state {}",
+                state_file.display()
+            )
+        })?;
+        debug!("Authentication state saved in {}", state_file.display());
+    }
+    Ok(client)
+}
+
+/// Authenticate to Bitwarden Secrets Manager with the configured access token
+pub async fn connect(config: &Config) -> Result<BitwardenClientWrapper> {
+    let client = match config.bw_persist_auth_state {
+        true => login_with_state(config).await?,
+        false => login(config, None).await?,
+    };
+
+    // Wrap the client in our Trait implementation
+    Ok(BitwardenClientWrapper {
+        client: Arc::new(client),
+        project_names: Arc::new(Mutex::new(HashMap::new())),
+        project_ids: Arc::new(
+            config
+                .bw_project_ids
+                .iter()
+                .map(|id| Uuid::parse_str(id))
+                .collect::<Result<_, _>>()?,
+        ),
+        organization_id: config
+            .bw_organization_id
+            .as_deref()
+            .map(Uuid::parse_str)
+            .transpose()?,
+    })
+}
@@ -0,0 +1,428 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::bitwarden::confirm::ask_password;
+use crate::config::Config;
+
+/// Seconds a request to `bw serve` may take before it is abandoned
+const REQUEST_TIMEOUT: u64 = 30;
+
+/// Settings of the `bw-serve` backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BwServeConfig {
+    /// Address `bw serve` listens on
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Whether to pull the vault from the server when the agent starts
+    #[serde(default = "default_sync")]
+    pub sync: bool,
+}
+
+fn default_url() -> String {
+    "http://localhost:8087".to_string()
+}
+
+fn default_sync() -> bool {
+    true
+}
+
+impl Default for BwServeConfig {
+    fn default() -> Self {
+        Self {
+            url: default_url(),
+            sync: default_sync(),
+        }
+    }
+}
+
+/// Location of a key: `<item ID>[#<attachment or field>]`. Without a name, the private
+/// key of an SSH key item, or the only attachment not ending in `.pub`, is used.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ItemRef {
+    pub item: Uuid,
+    pub source: Option<String>,
+}
+
+impl ItemRef {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let (item, source) = match reference.split_once('#') {
+            Some((item, source)) => (item, Some(source.to_string())),
+            None => (reference, None),
+        };
+        let item = Uuid::parse_str(item)
+            .ok()
+            .filter(|_| source.as_deref() != Some(""));
+        match item {
+            Some(item) => Ok(Self { item, source }),
+            None => bail!(
+                "Invalid Bitwarden item reference '{}', expected <item ID>[#<attachment or field>]",
+                reference
+            ),
+        }
+    }
+}
+
+/// Check the `bw_serve` section of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    reqwest::Url::parse(&config.bw_serve.url)
+        .with_context(|| format!("Invalid bw_serve.url '{}'", config.bw_serve.url))?;
+    Ok(())
+}
+
+/// Address of `bw serve`, for diagnostics
+pub fn url(config: &Config) -> String {
+    config.bw_serve.url.clone()
+}
+
+/// Unlock the vault if needed, asking for the master password
+pub async fn connect(config: &Config) -> Result<BwServeClient> {
+    BwServeClient::connect(&config.bw_serve, None).await
+}
+
+/// Body of every `bw serve` response except attachment downloads
+#[derive(Deserialize)]
+struct Reply<T> {
+    success: bool,
+    #[serde(default)]
+    message: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    template: StatusTemplate,
+}
+
+#[derive(Deserialize)]
+struct StatusTemplate {
+    status: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    name: String,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    fields: Option<Vec<ItemField>>,
+    #[serde(default)]
+    attachments: Option<Vec<Attachment>>,
+    #[serde(default)]
+    ssh_key: Option<SshKey>,
+}
+
+#[derive(Deserialize)]
+struct ItemField {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    value: Option<Zeroizing<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Attachment {
+    id: String,
+    file_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SshKey {
+    private_key: Zeroizing<String>,
+}
+
+/// Client of the Vault Management API of a local `bw serve`, reading keys from the
+/// Bitwarden Password Manager vault of the logged in user.
+///
+/// `bw serve` keeps the vault unlocked between requests. When it is found locked, e.g.
+/// after `bw lock`, the master password is asked for and dropped once the vault is
+/// unlocked.
+#[derive(Clone)]
+pub struct BwServeClient {
+    http: reqwest::Client,
+    url: String,
+    /// Master password given to `connect`, used for the next unlock only. The lock
+    /// also keeps concurrent fetches from unlocking the vault more than once.
+    password: Arc<Mutex<Option<Zeroizing<String>>>>,
+}
+
+impl BwServeClient {
+    /// Connect to `bw serve` and unlock the vault if needed, with `password` when given
+    pub async fn connect(
+        config: &BwServeConfig,
+        password: Option<Zeroizing<String>>,
+    ) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .user_agent(format!("vault-conductor/{}", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("Failed to create the bw serve HTTP client")?;
+        let client = Self {
+            http,
+            url: config.url.trim_end_matches('/').to_string(),
+            password: Arc::new(Mutex::new(password)),
+        };
+
+        client.unlock_if_locked().await?;
+        if config.sync {
+            // A stale local copy still serves the keys it has, so only warn
+            if let Err(e) = client.call::<serde_json::Value>(Method::POST, "sync").await {
+                warn!("bw serve: Failed to sync the vault: {:#}", e);
+            }
+        }
+        info!("Connected to bw serve at {}", client.url);
+        Ok(client)
+    }
+
+    /// Status of the vault: `unlocked`, `locked` or `unauthenticated`
+    async fn vault_status(&self) -> Result<String> {
+        Ok(self
+            .call::<Status>(Method::GET, "status")
+            .await?
+            .context("bw serve: Empty status response")?
+            .template
+            .status)
+    }
+
+    /// Whether `error` is a request `bw serve` refused because the vault is locked,
+    /// as told by its status rather than by the message of the refusal
+    async fn refused_as_locked(&self, error: &anyhow::Error) -> bool {
+        match refusal_status(error) {
+            Some(StatusCode::NOT_FOUND) | None => false,
+            Some(_) => matches!(self.vault_status().await.as_deref(), Ok("locked")),
+        }
+    }
+
+    /// Unlock the vault unless `bw serve` reports it unlocked
+    async fn unlock_if_locked(&self) -> Result<()> {
+        let mut password = self.password.lock().await;
+        let status = self.vault_status().await?;
+        match status.as_str() {
+            "unlocked" => return Ok(()),
+            "locked" => {}
+            "unauthenticated" => {
+                bail!("bw serve: No user is logged in, run `bw login` before starting `bw serve`")
+            }
+            status => bail!("bw serve: Unexpected vault status '{}'", status),
+        }
+
+        // Not kept once used: a vault locked again is unlocked by asking again
+        let password = match password.take() {
+            Some(password) => password,
+            None => ask_password("Master password of the Bitwarden vault: ").await?,
+        };
+        let body = serde_json::json!({
+            "password": password.as_str(),
+        });
+        let response = self
+            .http
+            .post(format!("{}/unlock", self.url))
+            .json(&body)
+            .send()
+            .await
+            .context("bw serve: Failed to reach the server")?;
+        if let Err(e) = read_reply::<serde_json::Value>(response).await {
+            return Err(e.context("bw serve: Failed to unlock the vault"));
+        }
+        info!("Unlocked the Bitwarden vault");
+        Ok(())
+    }
+
+    async fn send(&self, method: Method, path: &str) -> Result<reqwest::Response> {
+        self.http
+            .request(method, format!("{}/{}", self.url, path))
+            .send()
+            .await
+            .context("bw serve: Failed to reach the server")
+    }
+
+    /// Call an endpoint answering JSON
+    async fn call<T: DeserializeOwned>(&self, method: Method, path: &str) -> Result<Option<T>> {
+        read_reply(self.send(method, path).await?).await
+    }
+
+    /// Call an endpoint, unlocking the vault and trying again if it was locked meanwhile
+    async fn call_unlocked<T: DeserializeOwned>(&self, path: &str) -> Result<Option<T>> {
+        match self.call(Method::GET, path).await {
+            Err(e) if self.refused_as_locked(&e).await => {
+                info!("Bitwarden vault is locked, unlocking it");
+                self.unlock_if_locked().await?;
+                self.call(Method::GET, path).await
+            }
+            result => result,
+        }
+    }
+
+    /// Download an attachment, unlocking the vault and trying again if it was locked
+    async fn download(&self, item: &Uuid, attachment: &Attachment) -> Result<Zeroizing<String>> {
+        let path = format!("object/attachment/{}?itemid={}", attachment.id, item);
+        let bytes = match self.read_bytes(&path).await {
+            Err(e) if self.refused_as_locked(&e).await => {
+                self.unlock_if_locked().await?;
+                self.read_bytes(&path).await
+            }
+            result => result,
+        }
+        .with_context(|| format!("bw serve: Failed to download '{}'", attachment.file_name))?;
+        let text = std::str::from_utf8(&bytes).with_context(|| {
+            format!(
+                "bw serve: Attachment '{}' of item {} is not a text key",
+                attachment.file_name, item
+            )
+        })?;
+        Ok(Zeroizing::new(text.to_string()))
+    }
+
+    async fn read_bytes(&self, path: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let response = self.send(Method::GET, path).await?;
+        let status = response.status();
+        let body = Zeroizing::new(
+            response
+                .bytes()
+                .await
+                .context("bw serve: Failed to read the response")?
+                .to_vec(),
+        );
+        refused(status, &body)?;
+        Ok(body)
+    }
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for BwServeClient {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let reference = ItemRef::parse(id.as_str())?;
+        let item: Item = self
+            .call_unlocked(&format!("object/item/{}", reference.item))
+            .await
+            .map_err(|e| match refusal_status(&e) {
+                Some(StatusCode::NOT_FOUND) => {
+                    anyhow!("bw serve: Item {} not found in the vault", reference.item)
+                }
+                _ => e.context(format!("bw serve: Failed to read item {}", reference.item)),
+            })?
+            .with_context(|| format!("bw serve: Empty response for item {}", reference.item))?;
+
+        let attachments = item.attachments.unwrap_or_default();
+        let value = match &reference.source {
+            Some(name) => match attachments.iter().find(|a| &a.file_name == name) {
+                Some(attachment) => self.download(&reference.item, attachment).await?,
+                None => item
+                    .fields
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|field| field.name.as_deref() == Some(name))
+                    .and_then(|field| field.value)
+                    .with_context(|| {
+                        format!(
+                            "bw serve: Item '{}' has no attachment or field '{}'",
+                            item.name, name
+                        )
+                    })?,
+            },
+            None => match item.ssh_key {
+                Some(ssh_key) => ssh_key.private_key,
+                None => {
+                    let keys: Vec<&Attachment> = attachments
+                        .iter()
+                        .filter(|a| !a.file_name.ends_with(".pub"))
+                        .collect();
+                    if keys.len() != 1 {
+                        let mut names: Vec<&str> =
+                            keys.iter().map(|a| a.file_name.as_str()).collect();
+                        names.sort();
+                        bail!(
+                            "bw serve: Item '{}' is not an SSH key and has {} candidate key \
+                            attachments ({}), name the one to use as {}#<attachment>",
+                            item.name,
+                            keys.len(),
+                            names.join(", "),
+                            reference.item
+                        );
+                    }
+                    self.download(&reference.item, keys[0]).await?
+                }
+            },
+        };
+        debug!("Read key '{}' from bw serve", id);
+        Ok(SecretData {
+            name: item.name,
+            value,
+            note: item.notes.unwrap_or_default(),
+            project: None,
+        })
+    }
+}
+
+/// Message of a `{"success": false}` body
+fn failure(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice::<Reply<serde::de::IgnoredAny>>(body) {
+        Ok(reply) if !reply.success => Some(
+            reply
+                .message
+                .unwrap_or_else(|| "bw serve: Request failed".to_string()),
+        ),
+        _ => None,
+    }
+}
+
+/// Request `bw serve` answered with a failure, with the HTTP status of the response
+#[derive(Debug)]
+struct Refusal {
+    status: StatusCode,
+    message: String,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Fail with a `Refusal` when the response reports a failure
+fn refused(status: StatusCode, body: &[u8]) -> Result<()> {
+    let message = match failure(body) {
+        Some(message) => message,
+        None if !status.is_success() => format!("bw serve: Request failed ({})", status),
+        None => return Ok(()),
+    };
+    Err(Refusal { status, message }.into())
+}
+
+/// HTTP status of the response when `bw serve` refused the request
+fn refusal_status(error: &anyhow::Error) -> Option<StatusCode> {
+    error
+        .downcast_ref::<Refusal>()
+        .map(|refusal| refusal.status)
+}
+
+/// Parse a JSON reply, which may hold key material and is wiped once parsed
+async fn read_reply<T: DeserializeOwned>(response: reqwest::Response) -> Result<Option<T>> {
+    let status = response.status();
+    let body = Zeroizing::new(
+        response
+            .bytes()
+            .await
+            .context("bw serve: Failed to read the response")?
+            .to_vec(),
+    );
+    refused(status, &body)?;
+    let reply: Reply<T> =
+        serde_json::from_slice(&body).context("bw serve: Unexpected response format")?;
+    Ok(reply.data)
+}
@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use super::{BackendKind, SecretBackend};
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::config::{Config, KeySource};
+
+/// Seconds between two attempts to connect to an unreachable backend
+const RECONNECT_INTERVAL: u64 = 60;
+
+/// Connection to a backend, retried on use when it failed
+struct Connection {
+    backend: Option<SecretBackend>,
+    failed_at: Option<Instant>,
+    error: String,
+}
+
+impl Connection {
+    fn new(backend: Option<SecretBackend>) -> Self {
+        Self {
+            backend,
+            failed_at: None,
+            error: String::new(),
+        }
+    }
+
+    /// Backend, or the last error while it failed recently; `None` when a new attempt is due
+    fn ready(&self) -> Option<Result<SecretBackend>> {
+        if let Some(backend) = &self.backend {
+            return Some(Ok(backend.clone()));
+        }
+        if self
+            .failed_at
+            .is_some_and(|at| at.elapsed() < Duration::from_secs(RECONNECT_INTERVAL))
+        {
+            return Some(Err(anyhow!("{}", self.error)));
+        }
+        None
+    }
+
+    /// Records the outcome of a connection attempt
+    fn store(&mut self, result: &Result<SecretBackend>) {
+        match result {
+            Ok(backend) => self.backend = Some(backend.clone()),
+            Err(e) => {
+                self.failed_at = Some(Instant::now());
+                self.error = format!("{:#}", e);
+            }
+        }
+    }
+}
+
+/// Configured backend, backed by per-key fallback sources.
+///
+/// A key whose `key_options` list `fallbacks` is read from them in order when the backend
+/// fails to return it, e.g. when offline. The agent checks the pinned fingerprint of the
+/// key whatever source it came from. Fallback backends are connected on first use, and an
+/// unreachable backend is connected again at most every `RECONNECT_INTERVAL` seconds.
+#[derive(Clone)]
+pub struct FallbackBackend {
+    config: Arc<Config>,
+    fallbacks: Arc<HashMap<SecretId, Vec<KeySource>>>,
+    primary: Arc<Mutex<Connection>>,
+    sources: Arc<Mutex<HashMap<BackendKind, Connection>>>,
+}
+
+impl FallbackBackend {
+    /// `primary` is `None` when the configured backend could not be connected to
+    pub fn new(config: &Config, primary: Option<SecretBackend>) -> Self {
+        let fallbacks = config
+            .key_options
+            .iter()
+            .filter(|(_, options)| !options.fallbacks.is_empty())
+            .map(|(id, options)| (SecretId::new(id), options.fallbacks.clone()))
+            .collect();
+        let mut connection = Connection::new(primary);
+        if connection.backend.is_none() {
+            connection.failed_at = Some(Instant::now());
+            connection.error = format!("{} backend is unreachable", config.backend.name());
+        }
+        Self {
+            config: Arc::new(config.clone()),
+            fallbacks: Arc::new(fallbacks),
+            primary: Arc::new(Mutex::new(connection)),
+            sources: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn primary(&self) -> Result<SecretBackend> {
+        let ready = self.primary.lock().await.ready();
+        if let Some(ready) = ready {
+            return ready;
+        }
+        let kind = self.config.backend;
+        let result = connect(kind, &self.config).await;
+        self.primary.lock().await.store(&result);
+        result
+    }
+
+    async fn source(&self, kind: BackendKind) -> Result<SecretBackend> {
+        let ready = self
+            .sources
+            .lock()
+            .await
+            .entry(kind)
+            .or_insert_with(|| Connection::new(None))
+            .ready();
+        if let Some(ready) = ready {
+            return ready;
+        }
+        let result = connect(kind, &self.config).await;
+        if let Some(connection) = self.sources.lock().await.get_mut(&kind) {
+            connection.store(&result);
+        }
+        result
+    }
+}
+
+/// Connects to the backend of `kind`; called without holding a connection lock, so that a
+/// slow backend does not block the agent's other requests
+async fn connect(kind: BackendKind, config: &Config) -> Result<SecretBackend> {
+    let backend = kind.connect(config).await?;
+    info!("Connected to {} backend", kind.name());
+    Ok(backend)
+}
+
+#[async_trait]
+impl SecretFetcher for FallbackBackend {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let primary = match self.primary().await {
+            Ok(backend) => backend.get_secret(id).await,
+            Err(e) => Err(e),
+        };
+        let error = match (primary, self.fallbacks.get(id)) {
+            (Ok(secret), _) => return Ok(secret),
+            (Err(e), None) => return Err(e),
+            (Err(e), Some(_)) => e,
+        };
+        warn!(
+            "Failed to read secret {} from the {} backend, trying its fallbacks: {:#}",
+            id,
+            self.config.backend.name(),
+            error
+        );
+
+        let mut errors = vec![format!("{}: {:#}", self.config.backend.name(), error)];
+        for source in self.fallbacks.get(id).into_iter().flatten() {
+            let result = match self.source(source.backend).await {
+                Ok(backend) => backend.get_secret(&SecretId::new(&source.id)).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(secret) => {
+                    info!(
+                        "Read secret {} from fallback {} ({})",
+                        id,
+                        source.id,
+                        source.backend.name()
+                    );
+                    return Ok(secret);
+                }
+                Err(e) => errors.push(format!("{} {}: {:#}", source.backend.name(), source.id, e)),
+            }
+        }
+        Err(anyhow!(
+            "No source could return secret {}:\n{}",
+            id,
+            errors.join("\n")
+        ))
+    }
+
+    async fn discover_secrets(&self) -> Result<Vec<SecretId>> {
+        self.primary().await?.discover_secrets().await
+    }
+
+    async fn resolve_references(
+        &self,
+        references: &[String],
+    ) -> Result<HashMap<String, Vec<SecretId>>> {
+        self.primary().await?.resolve_references(references).await
+    }
+}
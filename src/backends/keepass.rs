@@ -0,0 +1,284 @@
+use anyhow::{anyhow, bail, Context, Result};
+use keepass::db::{fields, EntryId, EntryRef};
+use keepass::error::{DatabaseKeyError, DatabaseOpenError};
+use keepass::{Database, DatabaseKey};
+use log::{debug, info};
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::bitwarden::confirm::ask_password;
+use crate::config::Config;
+
+/// Settings of the `keepass` backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeePassConfig {
+    /// Path of the `.kdbx` database
+    #[serde(default)]
+    pub database: Option<String>,
+    /// Key file unlocking the database, alone or with the password
+    #[serde(default)]
+    pub key_file: Option<String>,
+    /// Whether the database has a password, asked for when the agent starts
+    #[serde(default = "default_password")]
+    pub password: bool,
+}
+
+fn default_password() -> bool {
+    true
+}
+
+impl Default for KeePassConfig {
+    fn default() -> Self {
+        Self {
+            database: None,
+            key_file: None,
+            password: default_password(),
+        }
+    }
+}
+
+/// Entry holding a key, by UUID or by path of group names and title
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryLocator {
+    Uuid(Uuid),
+    Path(Vec<String>),
+}
+
+/// Location of a key: `<entry UUID or Group/Title>[#<attachment or field>]`.
+/// Without a name, the only attachment of the entry not ending in `.pub` is used.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeyLocation {
+    pub entry: EntryLocator,
+    pub source: Option<String>,
+}
+
+impl KeyLocation {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let (entry, source) = match reference.split_once('#') {
+            Some((entry, source)) => (entry, Some(source.to_string())),
+            None => (reference, None),
+        };
+        if entry.is_empty() || source.as_deref() == Some("") {
+            bail!(
+                "Invalid KeePass key reference '{}', expected \
+                <entry UUID or Group/Title>[#<attachment or field>]",
+                reference
+            );
+        }
+        let entry = match Uuid::parse_str(entry) {
+            Ok(uuid) => EntryLocator::Uuid(uuid),
+            Err(_) => EntryLocator::Path(
+                entry
+                    .trim_matches('/')
+                    .split('/')
+                    .map(str::to_string)
+                    .collect(),
+            ),
+        };
+        Ok(Self { entry, source })
+    }
+}
+
+/// Check the `keepass` section of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    let keepass = &config.keepass;
+    if keepass.database.is_none() {
+        bail!("keepass.database must be set to the path of the .kdbx file");
+    }
+    if !keepass.password && keepass.key_file.is_none() {
+        bail!("The KeePass database needs a password, a key_file or both");
+    }
+    Ok(())
+}
+
+/// Path of the configured database, for diagnostics
+pub fn database_path(config: &Config) -> String {
+    config.keepass.database.clone().unwrap_or_default()
+}
+
+/// Ask for the password if the database has one, then check that the database opens
+pub async fn connect(config: &Config) -> Result<KeePassClient> {
+    let keepass = &config.keepass;
+    let database = PathBuf::from(
+        keepass
+            .database
+            .as_ref()
+            .context("keepass.database is not set")?,
+    );
+    let password = match keepass.password {
+        true => Some(
+            ask_password(&format!(
+                "Password of KeePass database {}: ",
+                database.display()
+            ))
+            .await?,
+        ),
+        false => None,
+    };
+    KeePassClient::open(
+        database,
+        password,
+        keepass.key_file.as_ref().map(PathBuf::from),
+    )
+    .await
+}
+
+/// Reader of keys stored in a KeePass (KDBX) database.
+///
+/// Only the credentials are kept in memory: the database is decrypted again for every
+/// fetch and dropped right after, so other entries do not stay in memory. Each fetch
+/// therefore runs the key derivation of the database (e.g. Argon2) again, costing as much
+/// time and memory as unlocking it in KeePassXC; a refresh pays it once per key.
+#[derive(Clone)]
+pub struct KeePassClient {
+    database: PathBuf,
+    key: Arc<DatabaseKey>,
+}
+
+impl KeePassClient {
+    pub async fn open(
+        database: PathBuf,
+        password: Option<Zeroizing<String>>,
+        key_file: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut key = DatabaseKey::new();
+        if let Some(password) = &password {
+            key = key.with_password(password);
+        }
+        if let Some(path) = &key_file {
+            let mut file = File::open(path)
+                .with_context(|| format!("Failed to open KeePass key file {}", path.display()))?;
+            key = key
+                .with_keyfile(&mut file)
+                .with_context(|| format!("Failed to read KeePass key file {}", path.display()))?;
+        }
+        let client = Self {
+            database,
+            key: Arc::new(key),
+        };
+
+        // Fail at startup on a wrong password rather than on the first signature
+        let entries = client.with_database(|db| Ok(db.num_entries())).await?;
+        info!(
+            "Opened KeePass database {} ({} entries)",
+            client.database.display(),
+            entries
+        );
+        Ok(client)
+    }
+
+    /// Decrypt the database, running its key derivation, and run `read` on it, off the
+    /// async runtime
+    async fn with_database<T, F>(&self, read: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T> + Send + 'static,
+    {
+        let (path, key) = (self.database.clone(), self.key.clone());
+        tokio::task::spawn_blocking(move || read(&open_database(&path, &key)?))
+            .await
+            .context("KeePass: Database reader failed")?
+    }
+}
+
+fn open_database(path: &Path, key: &DatabaseKey) -> Result<Database> {
+    let mut file = File::open(path)
+        .with_context(|| format!("KeePass: Failed to open database {}", path.display()))?;
+    Database::open(&mut file, key.clone()).map_err(|e| match e {
+        DatabaseOpenError::Key(DatabaseKeyError::IncorrectKey) => anyhow!(
+            "KeePass: Wrong password or key file for database {}",
+            path.display()
+        ),
+        e => anyhow!(
+            "KeePass: Failed to open database {}.\nError: {}",
+            path.display(),
+            e
+        ),
+    })
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for KeePassClient {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let location = KeyLocation::parse(id.as_str())?;
+        let reference = id.to_string();
+        self.with_database(move |db| {
+            let entry = find_entry(db, &location.entry)
+                .ok_or_else(|| anyhow!("KeePass: Entry '{}' not found", reference))?;
+            let value = read_key(&entry, location.source.as_deref(), &reference)?;
+            let parent = entry.parent();
+            debug!("Read key '{}' from KeePass", reference);
+            Ok(SecretData {
+                name: entry.get_title().unwrap_or(&reference).to_string(),
+                value,
+                note: entry.get(fields::NOTES).unwrap_or_default().to_string(),
+                project: (parent.id() != db.root().id()).then(|| parent.name.clone()),
+            })
+        })
+        .await
+    }
+}
+
+fn find_entry<'a>(db: &'a Database, locator: &EntryLocator) -> Option<EntryRef<'a>> {
+    match locator {
+        EntryLocator::Uuid(uuid) => db.entry(EntryId::from_uuid(*uuid)),
+        EntryLocator::Path(path) => {
+            let (title, groups) = path.split_last()?;
+            let groups: Vec<&str> = groups.iter().map(String::as_str).collect();
+            let id = db.root().group_by_path(&groups)?.entry_by_name(title)?.id();
+            db.entry(id)
+        }
+    }
+}
+
+/// Key text from the attachment or field named `source`, or from the only private key
+/// attachment of the entry
+fn read_key(entry: &EntryRef, source: Option<&str>, reference: &str) -> Result<Zeroizing<String>> {
+    let (name, bytes) = match source {
+        Some(name) => match entry.attachment_by_name(name) {
+            Some(attachment) => (name.to_string(), attachment.data.get().clone()),
+            None => match entry.get(name) {
+                Some(value) => return Ok(Zeroizing::new(value.to_string())),
+                None => bail!(
+                    "KeePass: Entry '{}' has no attachment or field '{}'",
+                    reference,
+                    name
+                ),
+            },
+        },
+        None => {
+            let mut keys: Vec<_> = entry
+                .attachments_named()
+                .filter(|(name, _)| !name.ends_with(".pub"))
+                .collect();
+            if keys.len() != 1 {
+                let mut names: Vec<&str> = keys.iter().map(|(name, _)| *name).collect();
+                names.sort();
+                bail!(
+                    "KeePass: Entry '{}' has {} candidate key attachments ({}), \
+                    name the one to use as {}#<attachment>",
+                    reference,
+                    keys.len(),
+                    names.join(", "),
+                    reference
+                );
+            }
+            let (name, attachment) = keys.remove(0);
+            (name.to_string(), attachment.data.get().clone())
+        }
+    };
+    let bytes = Zeroizing::new(bytes);
+    let text = std::str::from_utf8(&bytes).with_context(|| {
+        format!(
+            "KeePass: Attachment '{}' of entry '{}' is not a text key",
+            name, reference
+        )
+    })?;
+    Ok(Zeroizing::new(text.to_string()))
+}
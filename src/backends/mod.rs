@@ -0,0 +1,252 @@
+pub mod age;
+pub mod bitwarden;
+pub mod bw_serve;
+pub mod fallback;
+pub mod keepass;
+pub mod plugin;
+pub mod vault;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::config::Config;
+use fallback::FallbackBackend;
+
+/// Secret stores the keys can be loaded from, selected by `backend` in the configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Bitwarden Secrets Manager, authenticated with a machine account access token
+    #[default]
+    Bitwarden,
+    /// Bitwarden Password Manager vault, through the REST API of a local `bw serve`
+    #[serde(rename = "bw-serve")]
+    BwServe,
+    /// HashiCorp Vault or OpenBao KV engine, authenticated with a token or AppRole
+    Vault,
+    /// KeePass (KDBX) database file, unlocked with a password, a key file or both
+    KeePass,
+    /// age-encrypted file or directory, decrypted with a passphrase or an identity file
+    Age,
+    /// External executable answering a line-delimited JSON protocol on stdin/stdout
+    Plugin,
+}
+
+impl BackendKind {
+    /// Name of the backend, as written in the configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Bitwarden => "bitwarden",
+            BackendKind::BwServe => "bw-serve",
+            BackendKind::Vault => "vault",
+            BackendKind::KeePass => "keepass",
+            BackendKind::Age => "age",
+            BackendKind::Plugin => "plugin",
+        }
+    }
+
+    /// Check the settings the backend needs and that every configured secret is
+    /// referenced in its format
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        self.validate_settings(config)?;
+        for reference in config.secret_references() {
+            self.check_reference(config, reference)?;
+        }
+        Ok(())
+    }
+
+    /// Check the settings the backend needs, e.g. its credentials
+    pub fn validate_settings(&self, config: &Config) -> Result<()> {
+        match self {
+            BackendKind::Bitwarden => bitwarden::validate(config),
+            BackendKind::BwServe => bw_serve::validate(config),
+            BackendKind::Vault => vault::validate(config),
+            BackendKind::KeePass => keepass::validate(config),
+            BackendKind::Age => age::validate(config),
+            BackendKind::Plugin => plugin::validate(config),
+        }
+    }
+
+    /// Check that `reference` names a secret in the format of the backend
+    pub fn check_reference(&self, config: &Config, reference: &str) -> Result<()> {
+        match self {
+            // UUIDs, or names and patterns resolved when connecting
+            BackendKind::Bitwarden => Ok(()),
+            BackendKind::BwServe => bw_serve::ItemRef::parse(reference).map(|_| ()),
+            BackendKind::Vault => vault::SecretRef::parse(reference, &config.vault).map(|_| ()),
+            BackendKind::KeePass => keepass::KeyLocation::parse(reference).map(|_| ()),
+            BackendKind::Age => age::check_name(reference),
+            // The plugin defines the format of its references
+            BackendKind::Plugin => Ok(()),
+        }
+    }
+
+    /// Whether `reference` is resolved by the backend once connected, e.g. a Bitwarden
+    /// secret name
+    pub fn resolves(&self, reference: &str) -> bool {
+        match self {
+            BackendKind::Bitwarden => bitwarden::is_secret_name(reference),
+            _ => false,
+        }
+    }
+
+    /// Authenticate to the backend
+    pub async fn connect(&self, config: &Config) -> Result<SecretBackend> {
+        match self {
+            BackendKind::Bitwarden => Ok(SecretBackend::new(bitwarden::connect(config).await?)),
+            BackendKind::BwServe => Ok(SecretBackend::new(bw_serve::connect(config).await?)),
+            BackendKind::Vault => Ok(SecretBackend::new(vault::connect(config).await?)),
+            BackendKind::KeePass => Ok(SecretBackend::new(keepass::connect(config).await?)),
+            BackendKind::Age => Ok(SecretBackend::new(age::connect(config).await?)),
+            BackendKind::Plugin => Ok(SecretBackend::new(plugin::connect(config).await?)),
+        }
+    }
+
+    /// Where the secrets are read from, for diagnostics
+    pub fn endpoint(&self, config: &Config) -> String {
+        match self {
+            BackendKind::Bitwarden => bitwarden::api_url(config),
+            BackendKind::BwServe => bw_serve::url(config),
+            BackendKind::Vault => vault::address(config),
+            BackendKind::KeePass => keepass::database_path(config),
+            BackendKind::Age => age::store_path(config),
+            BackendKind::Plugin => plugin::command_line(config),
+        }
+    }
+}
+
+/// Authenticate to the configured backend and resolve the secret references of
+/// `config`. With fallback sources configured, an unreachable backend does not prevent
+/// serving the keys that have fallbacks.
+pub async fn connect(config: &mut Config) -> Result<SecretBackend> {
+    if !has_fallbacks(config) {
+        let backend = config.backend.connect(config).await?;
+        resolve_references(config, &backend).await?;
+        return Ok(backend);
+    }
+    let primary = match config.backend.connect(config).await {
+        Ok(backend) => {
+            resolve_references(config, &backend).await?;
+            Some(backend)
+        }
+        Err(e) if has_names(config) => {
+            return Err(e).context("Secret names cannot be resolved without the backend");
+        }
+        Err(e) => {
+            warn!(
+                "Failed to connect to the {} backend, serving keys from their fallbacks \
+                until it is reachable: {:#}",
+                config.backend.name(),
+                e
+            );
+            None
+        }
+    };
+    Ok(SecretBackend::new(FallbackBackend::new(config, primary)))
+}
+
+/// Add the fallback sources of the configuration to a connected backend
+pub fn with_fallbacks(config: &Config, backend: SecretBackend) -> SecretBackend {
+    match has_fallbacks(config) {
+        true => SecretBackend::new(FallbackBackend::new(config, Some(backend))),
+        false => backend,
+    }
+}
+
+/// Replace the secret references of `config` that `fetcher` resolves, e.g. Bitwarden
+/// secret names, with the IDs of the secrets they designate. The options of a name
+/// apply to each of its secrets, unless they have options of their own.
+pub async fn resolve_references<F: SecretFetcher>(config: &mut Config, fetcher: &F) -> Result<()> {
+    let mut references: Vec<String> = Vec::new();
+    for reference in config.secret_references() {
+        if config.backend.resolves(reference) && !references.contains(reference) {
+            references.push(reference.clone());
+        }
+    }
+    if references.is_empty() {
+        return Ok(());
+    }
+    let resolved = fetcher.resolve_references(&references).await?;
+
+    config.bw_secret_ids = expand(&config.bw_secret_ids, &resolved);
+    for profile in config.profiles.values_mut() {
+        profile.secret_ids = expand(&profile.secret_ids, &resolved);
+    }
+    for (reference, ids) in &resolved {
+        if let Some(options) = config.key_options.remove(reference) {
+            for id in ids {
+                config
+                    .key_options
+                    .entry(id.to_string())
+                    .or_insert_with(|| options.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `references` with the resolved ones replaced by their secrets, without duplicates
+fn expand(references: &[String], resolved: &HashMap<String, Vec<SecretId>>) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for reference in references {
+        let expanded = match resolved.get(reference) {
+            Some(secrets) => secrets.iter().map(SecretId::to_string).collect(),
+            None => vec![reference.clone()],
+        };
+        for id in expanded {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+fn has_names(config: &Config) -> bool {
+    config
+        .secret_references()
+        .any(|reference| config.backend.resolves(reference))
+}
+
+fn has_fallbacks(config: &Config) -> bool {
+    config
+        .key_options
+        .values()
+        .any(|options| !options.fallbacks.is_empty())
+}
+
+/// Backend selected at runtime, shared by the agent and its views
+#[derive(Clone)]
+pub struct SecretBackend(Arc<dyn SecretFetcher>);
+
+impl SecretBackend {
+    pub fn new<F: SecretFetcher>(fetcher: F) -> Self {
+        Self(Arc::new(fetcher))
+    }
+}
+
+#[async_trait]
+impl SecretFetcher for SecretBackend {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        self.0.get_secret(id).await
+    }
+
+    async fn discover_secrets(&self) -> Result<Vec<SecretId>> {
+        self.0.discover_secrets().await
+    }
+
+    async fn resolve_references(
+        &self,
+        references: &[String],
+    ) -> Result<HashMap<String, Vec<SecretId>>> {
+        self.0.resolve_references(references).await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests;
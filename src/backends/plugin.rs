@@ -0,0 +1,320 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::config::Config;
+
+/// Version of the protocol, sent with the health check
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Settings of the `plugin` backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    /// Executable serving the secrets, looked up in `PATH` unless it is a path
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Arguments passed to the executable
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds the plugin may take to answer a request
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            args: Vec::new(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+/// Request written to the plugin, one JSON object per line
+#[derive(Debug, Serialize)]
+#[serde(tag = "request", rename_all = "lowercase")]
+pub enum Request<'a> {
+    /// Check that the plugin works and can reach its store
+    Health { version: u32 },
+    /// IDs of the secrets the plugin can serve
+    List,
+    /// Content of one secret
+    Get { id: &'a str },
+}
+
+/// Answer of the plugin, one JSON object per line
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    error: Option<String>,
+    /// Kind of failure, `not_found` and `unauthorized` are reported as such
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+    #[serde(default)]
+    secret: Option<PluginSecret>,
+}
+
+#[derive(Deserialize)]
+struct PluginSecret {
+    #[serde(default)]
+    name: String,
+    value: Zeroizing<String>,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    project: Option<String>,
+}
+
+/// Failure reported by the plugin
+#[derive(Debug)]
+struct PluginError {
+    code: Option<String>,
+    message: String,
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// Check the `plugin` section of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    if config.plugin.command.is_none() {
+        bail!("plugin.command must be set to the executable serving the secrets");
+    }
+    if config.plugin.timeout == 0 {
+        bail!("plugin.timeout must be at least 1 second");
+    }
+    Ok(())
+}
+
+/// Command line of the plugin, for diagnostics
+pub fn command_line(config: &Config) -> String {
+    let plugin = &config.plugin;
+    std::iter::once(plugin.command.clone().unwrap_or_default())
+        .chain(plugin.args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Start the plugin, check its health, and warn about configured secrets it does not list
+pub async fn connect(config: &Config) -> Result<PluginClient> {
+    let client = PluginClient::start(&config.plugin).await?;
+    match client.list().await {
+        Ok(listed) => {
+            for id in config.secret_references() {
+                if !listed.contains(id) {
+                    warn!("Plugin: Secret '{}' is not listed by the plugin", id);
+                }
+            }
+        }
+        Err(e) => debug!("Plugin: Secrets not listed: {:#}", e),
+    }
+    Ok(client)
+}
+
+/// Running plugin process, with the pipes of the protocol
+struct PluginProcess {
+    // Killed when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+/// Reader of secrets served by an external executable over a line-delimited JSON
+/// protocol on its stdin and stdout.
+///
+/// The plugin is started once and answers one request at a time. A plugin that exits,
+/// answers garbage or does not answer in time is killed and started again on the next
+/// request.
+#[derive(Clone)]
+pub struct PluginClient {
+    config: PluginConfig,
+    process: Arc<Mutex<Option<PluginProcess>>>,
+}
+
+impl PluginClient {
+    pub async fn start(config: &PluginConfig) -> Result<Self> {
+        let client = Self {
+            config: config.clone(),
+            process: Arc::new(Mutex::new(None)),
+        };
+
+        // Fail at startup rather than on the first signature
+        client
+            .call(&Request::Health {
+                version: PROTOCOL_VERSION,
+            })
+            .await
+            .map_err(|e| match e.downcast_ref::<PluginError>() {
+                Some(error) => anyhow!("Plugin: Health check failed.\nError: {}", error.message),
+                None => e,
+            })?;
+        info!("Started plugin {}", client.name());
+        Ok(client)
+    }
+
+    fn name(&self) -> &str {
+        self.config.command.as_deref().unwrap_or_default()
+    }
+
+    /// IDs of the secrets the plugin can serve
+    pub async fn list(&self) -> Result<Vec<String>> {
+        Ok(self.call(&Request::List).await?.ids.unwrap_or_default())
+    }
+
+    fn spawn(&self) -> Result<PluginProcess> {
+        let command = self
+            .config
+            .command
+            .as_ref()
+            .context("plugin.command is not set")?;
+        let mut child = Command::new(command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Plugin: Failed to start '{}'", command))?;
+
+        // The plugin logs to stderr, forward it to our log
+        if let Some(stderr) = child.stderr.take() {
+            let name = command.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    warn!("Plugin {}: {}", name, line);
+                }
+            });
+        }
+        debug!("Spawned plugin {} (pid {:?})", command, child.id());
+        Ok(PluginProcess {
+            stdin: child.stdin.take().context("Plugin: No stdin pipe")?,
+            stdout: BufReader::new(child.stdout.take().context("Plugin: No stdout pipe")?),
+            _child: child,
+        })
+    }
+
+    /// Send a request and read the answer. Failures reported by the plugin are
+    /// `PluginError`s, the process is dropped on any other failure.
+    async fn call(&self, request: &Request<'_>) -> Result<Response> {
+        let mut slot = self.process.lock().await;
+        if slot.is_none() {
+            *slot = Some(self.spawn()?);
+        }
+        let process = slot.as_mut().expect("plugin process was just spawned");
+
+        let timeout = Duration::from_secs(self.config.timeout);
+        let exchange = tokio::time::timeout(timeout, exchange(process, request)).await;
+        let response = match exchange {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                slot.take();
+                return Err(e);
+            }
+            Err(_) => {
+                slot.take();
+                bail!(
+                    "Plugin: {} did not answer within {} seconds",
+                    self.name(),
+                    self.config.timeout
+                );
+            }
+        };
+        if !response.ok {
+            return Err(PluginError {
+                code: response.code,
+                message: response
+                    .error
+                    .unwrap_or_else(|| "no error message".to_string()),
+            }
+            .into());
+        }
+        Ok(response)
+    }
+}
+
+/// Write a request line and read the answer line
+async fn exchange(process: &mut PluginProcess, request: &Request<'_>) -> Result<Response> {
+    let mut line = serde_json::to_string(request).context("Plugin: Invalid request")?;
+    line.push('\n');
+    process
+        .stdin
+        .write_all(line.as_bytes())
+        .await
+        .context("Plugin: Failed to write the request, the plugin may have exited")?;
+    process.stdin.flush().await?;
+
+    // The answer may hold key material
+    let mut answer = Zeroizing::new(String::new());
+    let read = process
+        .stdout
+        .read_line(&mut answer)
+        .await
+        .context("Plugin: Failed to read the answer")?;
+    if read == 0 {
+        bail!("Plugin: The plugin exited without answering");
+    }
+    serde_json::from_str(&answer).context("Plugin: Unexpected answer format")
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for PluginClient {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let response = self
+            .call(&Request::Get { id: id.as_str() })
+            .await
+            .map_err(|e| {
+                let Some(error) = e.downcast_ref::<PluginError>() else {
+                    return e;
+                };
+                match error.code.as_deref() {
+                    Some("not_found") => anyhow!(
+                        "Plugin: Secret '{}' not found. \
+                        Please verify the secret ID exists and the plugin has access to it",
+                        id
+                    ),
+                    Some("unauthorized") => anyhow!(
+                        "Plugin: Access to secret '{}' denied.\nError: {}",
+                        id,
+                        error.message
+                    ),
+                    _ => anyhow!(
+                        "Plugin: Failed to fetch secret '{}'.\nError: {}",
+                        id,
+                        error.message
+                    ),
+                }
+            })?;
+        let secret = response
+            .secret
+            .with_context(|| format!("Plugin: No secret in the answer for '{}'", id))?;
+        Ok(SecretData {
+            name: secret.name,
+            value: secret.value,
+            note: secret.note,
+            project: secret.project,
+        })
+    }
+}
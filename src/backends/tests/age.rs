@@ -0,0 +1,140 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::age::{check_name, AgeClient, AgeKey};
+    use crate::backends::tests::common::temp_path;
+    use crate::bitwarden::agent::{BitwardenAgent, SecretFetcher, SecretId};
+    use crate::bitwarden::tests::common::ED25519_KEY;
+
+    use age::secrecy::{ExposeSecret, SecretString};
+    use age::x25519;
+    use ssh_agent_lib::agent::Session;
+    use std::fs;
+    use std::sync::Arc;
+    use zeroize::Zeroizing;
+
+    const PASSPHRASE: &str = "correct horse";
+
+    fn identity_key(identity: &x25519::Identity) -> AgeKey {
+        AgeKey::Identities(Zeroizing::new(format!(
+            "# test identity\n{}\n",
+            identity.to_string().expose_secret()
+        )))
+    }
+
+    /// Encrypt to a passphrase, with a low scrypt cost so tests stay fast
+    fn encrypt_with_passphrase(plaintext: &str) -> Vec<u8> {
+        let mut recipient = age::scrypt::Recipient::new(SecretString::from(PASSPHRASE.to_string()));
+        recipient.set_work_factor(2);
+        age::encrypt_and_armor(&recipient, plaintext.as_bytes())
+            .unwrap()
+            .into_bytes()
+    }
+
+    #[test]
+    fn key_names_cannot_leave_the_store() {
+        assert!(check_name("deploy_ed25519.v2").is_ok());
+        assert!(check_name("../deploy").is_err());
+        assert!(check_name("ssh/deploy").is_err());
+        assert!(check_name(".hidden").is_err());
+        assert!(check_name("").is_err());
+    }
+
+    #[tokio::test]
+    async fn directory_keys_are_read_with_an_identity_file() {
+        let identity = x25519::Identity::generate();
+        let dir = temp_path("age-dir");
+        fs::create_dir(&dir).unwrap();
+        for (name, key) in [("deploy", "DEPLOY KEY"), ("backup", "BACKUP KEY")] {
+            let ciphertext = age::encrypt(&identity.to_public(), key.as_bytes()).unwrap();
+            fs::write(dir.join(format!("{name}.age")), ciphertext).unwrap();
+        }
+
+        let client = AgeClient::open(dir.clone(), identity_key(&identity))
+            .await
+            .unwrap();
+        let secret = client.get_secret(&SecretId::new("deploy")).await.unwrap();
+        let missing = client
+            .get_secret(&SecretId::new("gone"))
+            .await
+            .err()
+            .expect("an unknown key should fail");
+
+        assert_eq!(*secret.value, "DEPLOY KEY");
+        assert_eq!(secret.name, "deploy");
+        assert!(missing.to_string().contains("Key 'gone' not found"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_entries_are_read_with_the_passphrase() {
+        let path = temp_path("age-file");
+        fs::write(
+            &path,
+            encrypt_with_passphrase(
+                "deploy: DEPLOY KEY\n\
+                 backup:\n  key: BACKUP KEY\n  note: |\n    [vault-conductor]\n    confirm = true\n",
+            ),
+        )
+        .unwrap();
+
+        let client = AgeClient::open(
+            path.clone(),
+            AgeKey::Passphrase(Zeroizing::new(PASSPHRASE.to_string())),
+        )
+        .await
+        .unwrap();
+        let plain = client.get_secret(&SecretId::new("deploy")).await.unwrap();
+        let detailed = client.get_secret(&SecretId::new("backup")).await.unwrap();
+
+        assert_eq!(*plain.value, "DEPLOY KEY");
+        assert!(plain.note.is_empty());
+        assert_eq!(*detailed.value, "BACKUP KEY");
+        assert!(detailed.note.contains("confirm = true"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_passphrase_fails_to_open() {
+        let path = temp_path("age-wrong");
+        fs::write(&path, encrypt_with_passphrase("deploy: KEY\n")).unwrap();
+
+        let error = AgeClient::open(
+            path.clone(),
+            AgeKey::Passphrase(Zeroizing::new("wrong".to_string())),
+        )
+        .await
+        .err()
+        .expect("a wrong passphrase should fail");
+
+        assert!(error.to_string().contains("Wrong passphrase or identity"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn agent_serves_keys_from_the_store() {
+        let identity = x25519::Identity::generate();
+        let path = temp_path("age-agent");
+        let store = format!(
+            "deploy: |\n{}",
+            ED25519_KEY
+                .lines()
+                .map(|line| format!("  {line}\n"))
+                .collect::<String>()
+        );
+        fs::write(
+            &path,
+            age::encrypt(&identity.to_public(), store.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+        let client = AgeClient::open(path.clone(), identity_key(&identity))
+            .await
+            .unwrap();
+        let mut agent = BitwardenAgent::new(Arc::new(client), vec![SecretId::new("deploy")]);
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].comment, "deploy");
+        fs::remove_file(path).unwrap();
+    }
+}
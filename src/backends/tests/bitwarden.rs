@@ -0,0 +1,142 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::bitwarden::{
+        auth_state_file, prepare_state_dir, resolve_reference, SecretName,
+    };
+    use crate::backends::resolve_references;
+    use crate::backends::tests::common::temp_path;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+    use crate::config::Config;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    fn secrets() -> Vec<SecretName> {
+        [
+            (1, "ssh-deploy", Some("infrastructure")),
+            (2, "ssh-backup", Some("infrastructure")),
+            (3, "ssh-deploy", Some("staging")),
+            (4, "database-password", None),
+        ]
+        .into_iter()
+        .map(|(n, key, project)| SecretName {
+            id: Uuid::from_u128(n),
+            key: key.to_string(),
+            project: project.map(str::to_string),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn names_and_patterns_are_resolved() {
+        let secrets = secrets();
+
+        assert_eq!(
+            resolve_reference("ssh-backup", &secrets).unwrap(),
+            vec![Uuid::from_u128(2)]
+        );
+        assert_eq!(
+            resolve_reference("staging/ssh-deploy", &secrets).unwrap(),
+            vec![Uuid::from_u128(3)]
+        );
+        assert_eq!(
+            resolve_reference("infrastructure/ssh-*", &secrets).unwrap(),
+            vec![Uuid::from_u128(1), Uuid::from_u128(2)]
+        );
+        assert_eq!(
+            resolve_reference("*/ssh-deplo?", &secrets).unwrap(),
+            vec![Uuid::from_u128(1), Uuid::from_u128(3)]
+        );
+        assert_eq!(
+            resolve_reference(&Uuid::from_u128(9).to_string(), &secrets).unwrap(),
+            vec![Uuid::from_u128(9)]
+        );
+        assert!(resolve_reference("gpg-*", &secrets).unwrap().is_empty());
+    }
+
+    #[test]
+    fn name_must_match_one_secret() {
+        let secrets = secrets();
+
+        let ambiguous = resolve_reference("ssh-deploy", &secrets)
+            .expect_err("a name of several secrets should fail");
+        let missing = resolve_reference("staging/ssh-backup", &secrets)
+            .expect_err("a name of no secret should fail");
+
+        assert!(ambiguous
+            .to_string()
+            .contains("Secret name 'ssh-deploy' is ambiguous, it matches 2 secrets"));
+        assert!(missing
+            .to_string()
+            .contains("No secret named 'staging/ssh-backup'"));
+    }
+
+    #[tokio::test]
+    async fn configuration_references_are_replaced() {
+        let mut config: Config = serde_yaml::from_str(&format!(
+            "bws_access_token: token\n\
+            bw_secret_ids: [deploy, '{id}']\n\
+            key_options:\n  deploy: {{confirm: true}}\n\
+            profiles:\n  ci: {{secret_ids: [backup]}}\n",
+            id = secret_id(1),
+        ))
+        .unwrap();
+        let fetcher = MockFetcher::default()
+            .with_secret(secret_id(1), "deploy", ED25519_KEY)
+            .with_secret(secret_id(2), "backup", RSA_2048_KEY);
+
+        resolve_references(&mut config, &fetcher).await.unwrap();
+
+        assert_eq!(config.bw_secret_ids, vec![secret_id(1).to_string()]);
+        assert_eq!(
+            config.profiles["ci"].secret_ids,
+            vec![secret_id(2).to_string()]
+        );
+        assert!(!config.key_options.contains_key("deploy"));
+        assert_eq!(
+            config.key_options[&secret_id(1).to_string()].confirm,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn each_token_and_server_get_their_auth_state() {
+        let config = |token: &str, endpoint: Option<&str>| -> Config {
+            let mut config: Config =
+                serde_yaml::from_str(&format!("bws_access_token: {token}\n")).unwrap();
+            config.bw_server_endpoint = endpoint.map(str::to_string);
+            config
+        };
+        let dir = Path::new("/state");
+
+        let path = auth_state_file(dir, &config("token-a", None));
+
+        assert_eq!(path, auth_state_file(dir, &config("token-a", None)));
+        assert_ne!(path, auth_state_file(dir, &config("token-b", None)));
+        assert_ne!(
+            path,
+            auth_state_file(dir, &config("token-a", Some("bitwarden.eu")))
+        );
+        assert!(path.starts_with(dir));
+        assert!(!path.to_string_lossy().contains("token-a"));
+    }
+
+    #[test]
+    fn state_dir_is_private() {
+        let created = temp_path("state-created").join("vault-conductor");
+        let existing = temp_path("state-existing");
+        fs::create_dir(&existing).unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o755)).unwrap();
+
+        prepare_state_dir(&created).unwrap();
+        prepare_state_dir(&existing).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&created), 0o700);
+        assert_eq!(mode(&existing), 0o700);
+        fs::remove_dir_all(created.parent().unwrap()).unwrap();
+        fs::remove_dir(existing).unwrap();
+    }
+}
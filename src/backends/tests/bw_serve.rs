@@ -0,0 +1,198 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::bw_serve::{BwServeClient, BwServeConfig, ItemRef};
+    use crate::backends::tests::common::StubServer;
+    use crate::bitwarden::agent::{SecretFetcher, SecretId};
+
+    use uuid::Uuid;
+    use zeroize::Zeroizing;
+
+    const ITEM: &str = "7c1a3f7e-94a2-4d0b-9a7e-2f6c1b0e5d41";
+    const UNLOCKED: &str =
+        r#"{"success":true,"data":{"object":"template","template":{"status":"unlocked"}}}"#;
+    const LOCKED: &str =
+        r#"{"success":true,"data":{"object":"template","template":{"status":"locked"}}}"#;
+    const MESSAGE: &str = r#"{"success":true,"data":{"object":"message","title":"Done"}}"#;
+
+    fn config(server: &StubServer) -> BwServeConfig {
+        BwServeConfig {
+            url: server.address.clone(),
+            sync: false,
+        }
+    }
+
+    fn password() -> Option<Zeroizing<String>> {
+        Some(Zeroizing::new("master password".to_string()))
+    }
+
+    #[test]
+    fn references_name_an_item_and_optionally_its_attachment() {
+        assert_eq!(
+            ItemRef::parse(&format!("{ITEM}#id_ed25519")).unwrap(),
+            ItemRef {
+                item: Uuid::parse_str(ITEM).unwrap(),
+                source: Some("id_ed25519".to_string()),
+            }
+        );
+        assert!(ItemRef::parse("GitHub").is_err());
+        assert!(ItemRef::parse(&format!("{ITEM}#")).is_err());
+    }
+
+    #[tokio::test]
+    async fn ssh_key_item_is_read_from_the_unlocked_vault() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/status", 200, UNLOCKED)
+            .respond("POST", "/sync", 200, MESSAGE)
+            .respond(
+                "GET",
+                &format!("/object/item/{ITEM}"),
+                200,
+                r#"{"success":true,"data":{"id":"7c1a3f7e-94a2-4d0b-9a7e-2f6c1b0e5d41",
+                "name":"GitHub","type":5,"notes":"[vault-conductor]\nconfirm = true",
+                "sshKey":{"privateKey":"SSH KEY","publicKey":"PUB","keyFingerprint":"SHA256:x"}}}"#,
+            );
+
+        let config = BwServeConfig {
+            sync: true,
+            ..config(&server)
+        };
+        let client = BwServeClient::connect(&config, None).await.unwrap();
+        let secret = client.get_secret(&SecretId::new(ITEM)).await.unwrap();
+
+        assert_eq!(*secret.value, "SSH KEY");
+        assert_eq!(secret.name, "GitHub");
+        assert!(secret.note.contains("confirm = true"));
+        assert_eq!(server.requests_to("/sync").len(), 1);
+        assert!(server.requests_to("/unlock").is_empty());
+    }
+
+    #[tokio::test]
+    async fn locked_vault_is_unlocked_and_key_attachment_downloaded() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/status", 200, LOCKED)
+            .respond("POST", "/unlock", 200, MESSAGE)
+            .respond(
+                "GET",
+                &format!("/object/item/{ITEM}"),
+                200,
+                r#"{"success":true,"data":{"name":"Deploy","type":1,"notes":null,
+                "attachments":[{"id":"a1","fileName":"id_ed25519.pub"},
+                {"id":"a2","fileName":"id_ed25519"}]}}"#,
+            )
+            .respond(
+                "GET",
+                &format!("/object/attachment/a2?itemid={ITEM}"),
+                200,
+                "ATTACHED KEY",
+            );
+
+        let client = BwServeClient::connect(&config(&server), password())
+            .await
+            .unwrap();
+        let secret = client.get_secret(&SecretId::new(ITEM)).await.unwrap();
+
+        assert_eq!(*secret.value, "ATTACHED KEY");
+        assert!(secret.note.is_empty());
+        let unlock = &server.requests_to("/unlock")[0];
+        assert!(unlock.body.contains(r#""password":"master password""#));
+    }
+
+    #[tokio::test]
+    async fn vault_locked_meanwhile_is_unlocked_again() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/status", 200, UNLOCKED)
+            .respond("GET", "/status", 200, LOCKED)
+            .respond("POST", "/unlock", 200, MESSAGE)
+            .respond(
+                "GET",
+                &format!("/object/item/{ITEM}"),
+                400,
+                r#"{"success":false,"message":"Vault is locked."}"#,
+            )
+            .respond(
+                "GET",
+                &format!("/object/item/{ITEM}"),
+                200,
+                r#"{"success":true,"data":{"name":"Deploy",
+                "fields":[{"name":"private_key","value":"FIELD KEY","type":1}]}}"#,
+            );
+
+        let client = BwServeClient::connect(&config(&server), password())
+            .await
+            .unwrap();
+        let secret = client
+            .get_secret(&SecretId::new(format!("{ITEM}#private_key")))
+            .await
+            .unwrap();
+
+        assert_eq!(*secret.value, "FIELD KEY");
+        assert_eq!(server.requests_to("/unlock").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn missing_item_is_reported() {
+        let server = StubServer::start().await;
+        server.respond("GET", "/status", 200, UNLOCKED).respond(
+            "GET",
+            &format!("/object/item/{ITEM}"),
+            404,
+            r#"{"success":false,"message":"Not found."}"#,
+        );
+
+        let client = BwServeClient::connect(&config(&server), None)
+            .await
+            .unwrap();
+        let error = client
+            .get_secret(&SecretId::new(ITEM))
+            .await
+            .err()
+            .expect("an unknown item should fail");
+
+        assert!(error.to_string().contains("not found in the vault"));
+    }
+
+    #[tokio::test]
+    async fn refusal_with_the_vault_unlocked_is_not_retried() {
+        let server = StubServer::start().await;
+        server.respond("GET", "/status", 200, UNLOCKED).respond(
+            "GET",
+            &format!("/object/item/{ITEM}"),
+            400,
+            r#"{"success":false,"message":"Item is locked for editing."}"#,
+        );
+
+        let client = BwServeClient::connect(&config(&server), None)
+            .await
+            .unwrap();
+        let error = client
+            .get_secret(&SecretId::new(ITEM))
+            .await
+            .err()
+            .expect("a refused item should fail");
+
+        assert!(format!("{:#}", error).contains("Item is locked for editing."));
+        assert_eq!(server.requests_to(&format!("/object/item/{ITEM}")).len(), 1);
+        assert!(server.requests_to("/unlock").is_empty());
+    }
+
+    #[tokio::test]
+    async fn logged_out_cli_fails_to_connect() {
+        let server = StubServer::start().await;
+        server.respond(
+            "GET",
+            "/status",
+            200,
+            r#"{"success":true,"data":{"template":{"status":"unauthenticated"}}}"#,
+        );
+
+        let error = BwServeClient::connect(&config(&server), None)
+            .await
+            .err()
+            .expect("a logged out CLI should fail");
+
+        assert!(error.to_string().contains("bw login"));
+    }
+}
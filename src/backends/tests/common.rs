@@ -0,0 +1,147 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Request received by a `StubServer`
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// Canned responses, keyed by `<METHOD> <path>`. The last response of a route repeats.
+type Routes = HashMap<String, VecDeque<(u16, String)>>;
+
+/// HTTP server answering canned JSON responses, standing in for a secret store
+pub struct StubServer {
+    pub address: String,
+    routes: Arc<Mutex<Routes>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl StubServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let routes: Arc<Mutex<Routes>> = Arc::default();
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Arc::default();
+
+        let (server_routes, server_requests) = (routes.clone(), requests.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (routes, requests) = (server_routes.clone(), server_requests.clone());
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut reader = BufReader::new(reader);
+                    // Connections are kept alive, answer requests until the client leaves
+                    while let Some(request) = read_request(&mut reader).await {
+                        let route = format!("{} {}", request.method, request.path);
+                        requests.lock().unwrap().push(request);
+                        let (status, body) = {
+                            let mut routes = routes.lock().unwrap();
+                            match routes.get_mut(&route) {
+                                Some(responses) if responses.len() > 1 => {
+                                    responses.pop_front().unwrap()
+                                }
+                                Some(responses) => responses[0].clone(),
+                                None => (404, r#"{"errors":[]}"#.to_string()),
+                            }
+                        };
+                        let response = format!(
+                            "HTTP/1.1 {} Stub\r\ncontent-type: application/json\r\n\
+                            content-length: {}\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        if writer.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        Self {
+            address,
+            routes,
+            requests,
+        }
+    }
+
+    /// Answer `status` and `body` to `<method> <path>`, after the responses already added
+    pub fn respond(&self, method: &str, path: &str, status: u16, body: &str) -> &Self {
+        self.routes
+            .lock()
+            .unwrap()
+            .entry(format!("{} {}", method, path))
+            .or_default()
+            .push_back((status, body.to_string()));
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Requests received for `path`
+    pub fn requests_to(&self, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|request| request.path == path)
+            .collect()
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<RecordedRequest> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await.ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+/// Path of a file in the temporary directory, unique to the test run
+pub fn temp_path(name: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before Unix epoch")
+        .as_nanos();
+    std::env::temp_dir().join(format!(
+        "vault-conductor-{name}-{}-{timestamp}",
+        std::process::id()
+    ))
+}
@@ -0,0 +1,145 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::fallback::FallbackBackend;
+    use crate::backends::tests::common::temp_path;
+    use crate::backends::SecretBackend;
+    use crate::bitwarden::agent::SecretFetcher;
+    use crate::bitwarden::daemon::build_agent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+    use crate::config::Config;
+
+    use age::secrecy::ExposeSecret;
+    use age::x25519;
+    use ssh_agent_lib::agent::Session;
+    use ssh_key::{HashAlg, PrivateKey};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// Local age store holding `key` as `deploy`, with its identity file
+    struct LocalStore {
+        store: PathBuf,
+        identity: PathBuf,
+    }
+
+    impl LocalStore {
+        fn new(name: &str, key: &str) -> Self {
+            let identity = x25519::Identity::generate();
+            let store = temp_path(name).with_extension("age");
+            let identity_file = temp_path(name).with_extension("txt");
+            let content = format!(
+                "deploy: |\n{}",
+                key.lines()
+                    .map(|line| format!("  {line}\n"))
+                    .collect::<String>()
+            );
+            fs::write(
+                &store,
+                age::encrypt(&identity.to_public(), content.as_bytes()).unwrap(),
+            )
+            .unwrap();
+            fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+            Self {
+                store,
+                identity: identity_file,
+            }
+        }
+
+        /// Bitwarden configuration pinning the ED25519 key of secret 1, with this store
+        /// as its fallback
+        fn config(&self) -> Config {
+            let fingerprint = PrivateKey::from_openssh(ED25519_KEY)
+                .unwrap()
+                .fingerprint(HashAlg::Sha256);
+            serde_yaml::from_str(&format!(
+                "bws_access_token: token\n\
+                bw_secret_ids: ['{id}']\n\
+                age: {{path: '{store}', identity_file: '{identity}'}}\n\
+                key_options:\n  '{id}':\n    fingerprint: '{fingerprint}'\n\
+                \x20   fallbacks: [{{backend: age, id: deploy}}]\n",
+                id = secret_id(1),
+                store = self.store.display(),
+                identity = self.identity.display(),
+            ))
+            .unwrap()
+        }
+    }
+
+    impl Drop for LocalStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.store);
+            let _ = fs::remove_file(&self.identity);
+        }
+    }
+
+    fn unreachable_primary() -> SecretBackend {
+        let primary = MockFetcher::default().with_secret(secret_id(1), "deploy", ED25519_KEY);
+        primary.make_unavailable(secret_id(1));
+        SecretBackend::new(primary)
+    }
+
+    #[tokio::test]
+    async fn key_is_served_from_its_fallback() {
+        let store = LocalStore::new("fallback-served", ED25519_KEY);
+        let config = store.config();
+        let backend = FallbackBackend::new(&config, Some(unreachable_primary()));
+
+        let mut agent = build_agent(&config, Arc::new(backend));
+
+        assert_eq!(agent.request_identities().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fallback_with_another_key_is_refused() {
+        let store = LocalStore::new("fallback-swapped", RSA_2048_KEY);
+        let config = store.config();
+        let backend = FallbackBackend::new(&config, Some(unreachable_primary()));
+
+        let agent = build_agent(&config, Arc::new(backend));
+        let checks = agent.check_keys().await;
+
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("a fallback key with another fingerprint should be refused");
+        assert!(error.contains("Fingerprint mismatch"));
+    }
+
+    #[tokio::test]
+    async fn unconnected_backend_is_not_retried_at_once() {
+        let store = LocalStore::new("fallback-offline", ED25519_KEY);
+        let config = store.config();
+        let backend = FallbackBackend::new(&config, None);
+
+        let secret = backend.get_secret(&secret_id(1)).await.unwrap();
+        let error = backend
+            .get_secret(&secret_id(2))
+            .await
+            .err()
+            .expect("a key without fallbacks should fail");
+
+        assert!(secret.value.contains("OPENSSH PRIVATE KEY"));
+        assert!(error
+            .to_string()
+            .contains("bitwarden backend is unreachable"));
+    }
+
+    #[tokio::test]
+    async fn every_source_failure_is_reported() {
+        let store = LocalStore::new("fallback-failed", ED25519_KEY);
+        let mut config = store.config();
+        config.age.identity_file = Some(temp_path("missing-identity").display().to_string());
+        let backend = FallbackBackend::new(&config, Some(unreachable_primary()));
+
+        let error = backend
+            .get_secret(&secret_id(1))
+            .await
+            .err()
+            .expect("failing sources should fail");
+
+        let message = error.to_string();
+        assert!(message.contains("No source could return secret"));
+        assert!(message.contains("bitwarden:"));
+        assert!(message.contains("age deploy: Failed to read age identity file"));
+    }
+}
@@ -0,0 +1,153 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::keepass::{EntryLocator, KeePassClient, KeyLocation};
+    use crate::backends::tests::common::temp_path;
+    use crate::bitwarden::agent::{SecretFetcher, SecretId};
+
+    use keepass::config::{DatabaseConfig, KdfConfig};
+    use keepass::db::{fields, Value};
+    use keepass::{Database, DatabaseKey};
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use uuid::Uuid;
+    use zeroize::Zeroizing;
+
+    const PASSWORD: &str = "correct horse";
+
+    /// Database with `Servers/Deploy` holding a key attachment next to its public key,
+    /// and a root entry holding two keys, one of them in a custom field. Returns the path
+    /// of the database and the UUID of the root entry.
+    fn create_database(name: &str, key: DatabaseKey) -> (PathBuf, Uuid) {
+        // A fast KDF, tests open the database many times
+        let mut config = DatabaseConfig::default();
+        config.kdf_config = KdfConfig::Aes { rounds: 100 };
+        let mut db = Database::with_config(config);
+        let mut root = db.root_mut();
+        {
+            let mut group = root.add_group();
+            group.name = "Servers".to_string();
+            let mut deploy = group.add_entry();
+            deploy.set_unprotected(fields::TITLE, "Deploy");
+            deploy.set_unprotected(fields::NOTES, "[vault-conductor]\nconfirm = true");
+            deploy.add_attachment("id_ed25519", Value::protected(b"DEPLOY KEY".to_vec()));
+            deploy.add_attachment("id_ed25519.pub", Value::unprotected(b"PUBLIC".to_vec()));
+        }
+        let mut personal = root.add_entry();
+        personal.set_unprotected(fields::TITLE, "Personal");
+        personal.set_protected("ssh_key", "FIELD KEY");
+        personal.add_attachment("work", Value::protected(b"WORK KEY".to_vec()));
+        personal.add_attachment("home", Value::protected(b"HOME KEY".to_vec()));
+        let personal_id = personal.id().uuid();
+
+        let path = temp_path(name).with_extension("kdbx");
+        db.save(&mut File::create(&path).unwrap(), key).unwrap();
+        (path, personal_id)
+    }
+
+    fn password() -> Option<Zeroizing<String>> {
+        Some(Zeroizing::new(PASSWORD.to_string()))
+    }
+
+    #[test]
+    fn references_locate_entries_by_uuid_or_path() {
+        let uuid = Uuid::from_u128(7);
+
+        assert_eq!(
+            KeyLocation::parse(&uuid.simple().to_string()).unwrap(),
+            KeyLocation {
+                entry: EntryLocator::Uuid(uuid),
+                source: None,
+            }
+        );
+        assert_eq!(
+            KeyLocation::parse("Servers/Deploy#id_ed25519").unwrap(),
+            KeyLocation {
+                entry: EntryLocator::Path(vec!["Servers".to_string(), "Deploy".to_string()]),
+                source: Some("id_ed25519".to_string()),
+            }
+        );
+        assert!(KeyLocation::parse("Servers/Deploy#").is_err());
+    }
+
+    #[tokio::test]
+    async fn key_attachment_is_read_by_path() {
+        let (path, _) = create_database("path", DatabaseKey::new().with_password(PASSWORD));
+
+        let client = KeePassClient::open(path.clone(), password(), None)
+            .await
+            .unwrap();
+        let secret = client
+            .get_secret(&SecretId::new("Servers/Deploy"))
+            .await
+            .unwrap();
+
+        assert_eq!(*secret.value, "DEPLOY KEY");
+        assert_eq!(secret.name, "Deploy");
+        assert_eq!(secret.project.as_deref(), Some("Servers"));
+        assert!(secret.note.contains("confirm = true"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn field_and_named_attachment_are_read_by_uuid() {
+        let key_file = temp_path("key-file");
+        fs::write(&key_file, b"key file content").unwrap();
+        let key = DatabaseKey::new()
+            .with_password(PASSWORD)
+            .with_keyfile(&mut File::open(&key_file).unwrap())
+            .unwrap();
+        let (path, uuid) = create_database("uuid", key);
+
+        let client = KeePassClient::open(path.clone(), password(), Some(key_file.clone()))
+            .await
+            .unwrap();
+        let field = client
+            .get_secret(&SecretId::new(format!("{}#ssh_key", uuid)))
+            .await
+            .unwrap();
+        let attachment = client
+            .get_secret(&SecretId::new(format!("{}#home", uuid)))
+            .await
+            .unwrap();
+
+        assert_eq!(*field.value, "FIELD KEY");
+        assert_eq!(*attachment.value, "HOME KEY");
+        assert_eq!(field.project, None);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(key_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn several_key_attachments_must_be_named() {
+        let (path, _) = create_database("ambiguous", DatabaseKey::new().with_password(PASSWORD));
+
+        let client = KeePassClient::open(path.clone(), password(), None)
+            .await
+            .unwrap();
+        let error = client
+            .get_secret(&SecretId::new("Personal"))
+            .await
+            .err()
+            .expect("an entry with several keys should need a name");
+
+        assert!(error.to_string().contains("(home, work)"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_password_fails_to_open() {
+        let (path, _) = create_database("wrong", DatabaseKey::new().with_password(PASSWORD));
+
+        let error = KeePassClient::open(
+            path.clone(),
+            Some(Zeroizing::new("wrong".to_string())),
+            None,
+        )
+        .await
+        .err()
+        .expect("a wrong password should fail");
+
+        assert!(error.to_string().contains("Wrong password or key file"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,8 @@
+pub mod age;
+pub mod bitwarden;
+pub mod bw_serve;
+pub mod common;
+pub mod fallback;
+pub mod keepass;
+pub mod plugin;
+pub mod vault;
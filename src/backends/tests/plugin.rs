@@ -0,0 +1,131 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::plugin::{PluginClient, PluginConfig};
+    use crate::backends::tests::common::temp_path;
+    use crate::bitwarden::agent::{SecretFetcher, SecretId};
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Plugin appending a line to the file given as argument each time it starts
+    const PLUGIN: &str = r#"echo started >> "$1"
+while IFS= read -r line; do
+  case "$line" in
+    *'"request":"health"'*) echo '{"ok":true}' ;;
+    *'"request":"list"'*) echo '{"ok":true,"ids":["deploy"]}' ;;
+    *'"id":"deploy"'*) echo '{"ok":true,"secret":{"name":"Deploy","value":"DEPLOY KEY","note":"confirm = true","project":"ops"}}' ;;
+    *'"id":"slow"'*) sleep 5; echo '{"ok":true}' ;;
+    *'"id":"crash"'*) exit 1 ;;
+    *'"id":"denied"'*) echo '{"ok":false,"code":"unauthorized","error":"session expired"}' ;;
+    *) echo '{"ok":false,"code":"not_found","error":"no such secret"}' ;;
+  esac
+done
+"#;
+
+    /// Plugin script written to a temporary file, run through `sh`
+    fn plugin(name: &str, script: &str) -> (PluginConfig, PathBuf, PathBuf) {
+        let path = temp_path(name).with_extension("sh");
+        let starts = temp_path(name).with_extension("starts");
+        fs::write(&path, script).unwrap();
+        let config = PluginConfig {
+            command: Some("sh".to_string()),
+            args: vec![path.display().to_string(), starts.display().to_string()],
+            timeout: 1,
+        };
+        (config, path, starts)
+    }
+
+    fn starts(path: &PathBuf) -> usize {
+        fs::read_to_string(path).unwrap_or_default().lines().count()
+    }
+
+    #[tokio::test]
+    async fn secret_is_read_from_the_plugin() {
+        let (config, script, started) = plugin("plugin-get", PLUGIN);
+
+        let client = PluginClient::start(&config).await.unwrap();
+        let secret = client.get_secret(&SecretId::new("deploy")).await.unwrap();
+
+        assert_eq!(*secret.value, "DEPLOY KEY");
+        assert_eq!(secret.name, "Deploy");
+        assert_eq!(secret.note, "confirm = true");
+        assert_eq!(secret.project.as_deref(), Some("ops"));
+        assert_eq!(client.list().await.unwrap(), vec!["deploy".to_string()]);
+        assert_eq!(starts(&started), 1);
+        fs::remove_file(script).unwrap();
+        fs::remove_file(started).unwrap();
+    }
+
+    #[tokio::test]
+    async fn plugin_errors_are_mapped() {
+        let (config, script, started) = plugin("plugin-errors", PLUGIN);
+
+        let client = PluginClient::start(&config).await.unwrap();
+        let missing = client
+            .get_secret(&SecretId::new("gone"))
+            .await
+            .err()
+            .expect("an unknown secret should fail");
+        let denied = client
+            .get_secret(&SecretId::new("denied"))
+            .await
+            .err()
+            .expect("a denied secret should fail");
+
+        assert!(missing.to_string().contains("Secret 'gone' not found"));
+        assert!(denied
+            .to_string()
+            .contains("Access to secret 'denied' denied"));
+        assert!(denied.to_string().contains("session expired"));
+        // Reported failures keep the plugin running
+        assert_eq!(starts(&started), 1);
+        fs::remove_file(script).unwrap();
+        fs::remove_file(started).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stuck_or_crashed_plugin_is_started_again() {
+        let (config, script, started) = plugin("plugin-restart", PLUGIN);
+
+        let client = PluginClient::start(&config).await.unwrap();
+        let slow = client
+            .get_secret(&SecretId::new("slow"))
+            .await
+            .err()
+            .expect("a stuck plugin should time out");
+        let crash = client
+            .get_secret(&SecretId::new("crash"))
+            .await
+            .err()
+            .expect("a crashed plugin should fail");
+        let secret = client.get_secret(&SecretId::new("deploy")).await.unwrap();
+
+        assert!(slow.to_string().contains("did not answer within 1 seconds"));
+        assert!(crash.to_string().contains("exited without answering"));
+        assert_eq!(*secret.value, "DEPLOY KEY");
+        assert_eq!(starts(&started), 3);
+        fs::remove_file(script).unwrap();
+        fs::remove_file(started).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_health_check_fails_to_start() {
+        let (config, script, started) = plugin(
+            "plugin-health",
+            r#"echo started >> "$1"
+read -r line
+echo '{"ok":false,"error":"not logged in"}'
+"#,
+        );
+
+        let error = PluginClient::start(&config)
+            .await
+            .err()
+            .expect("an unhealthy plugin should fail");
+
+        assert!(error.to_string().contains("Health check failed"));
+        assert!(error.to_string().contains("not logged in"));
+        fs::remove_file(script).unwrap();
+        fs::remove_file(started).unwrap();
+    }
+}
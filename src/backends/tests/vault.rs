@@ -0,0 +1,246 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::backends::tests::common::StubServer;
+    use crate::backends::vault::{AppRole, SecretRef, VaultClient, VaultConfig};
+    use crate::bitwarden::agent::{SecretFetcher, SecretId};
+
+    use zeroize::Zeroizing;
+
+    const KV2_MOUNT: &str = r#"{"data":{"type":"kv","options":{"version":"2"}}}"#;
+    const KV1_MOUNT: &str = r#"{"data":{"type":"kv","options":null}}"#;
+
+    fn config(server: &StubServer) -> VaultConfig {
+        VaultConfig {
+            address: Some(server.address.clone()),
+            token: Some(Zeroizing::new("test-token".to_string())),
+            ..VaultConfig::default()
+        }
+    }
+
+    #[test]
+    fn references_fall_back_to_the_default_mount_and_field() {
+        let config = VaultConfig::default();
+
+        assert_eq!(
+            SecretRef::parse("ssh/deploy", &config).unwrap(),
+            SecretRef {
+                mount: "secret".to_string(),
+                path: "ssh/deploy".to_string(),
+                field: "private_key".to_string(),
+            }
+        );
+        assert_eq!(
+            SecretRef::parse("kv:ops/bastion#key", &config).unwrap(),
+            SecretRef {
+                mount: "kv".to_string(),
+                path: "ops/bastion".to_string(),
+                field: "key".to_string(),
+            }
+        );
+        assert!(SecretRef::parse("kv:#key", &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn kv2_secret_is_read_with_the_token() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/v1/auth/token/lookup-self", 200, r#"{"data":{}}"#)
+            .respond("GET", "/v1/sys/internal/ui/mounts/secret", 200, KV2_MOUNT)
+            .respond(
+                "GET",
+                "/v1/secret/data/ssh/deploy",
+                200,
+                r#"{"data":{"data":{"private_key":"KEY","note":"comment: deploy","size":1},
+                "metadata":{"version":3}}}"#,
+            );
+
+        let client = VaultClient::connect(&config(&server)).await.unwrap();
+        let secret = client
+            .get_secret(&SecretId::new("ssh/deploy"))
+            .await
+            .unwrap();
+
+        assert_eq!(*secret.value, "KEY");
+        assert_eq!(secret.name, "ssh/deploy");
+        assert_eq!(secret.note, "comment: deploy");
+        assert_eq!(secret.project.as_deref(), Some("secret"));
+        let read = &server.requests_to("/v1/secret/data/ssh/deploy")[0];
+        assert_eq!(read.headers["x-vault-token"], "test-token");
+    }
+
+    #[tokio::test]
+    async fn kv1_mount_is_detected_once() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/v1/auth/token/lookup-self", 200, r#"{"data":{}}"#)
+            .respond("GET", "/v1/sys/internal/ui/mounts/legacy", 200, KV1_MOUNT)
+            .respond(
+                "GET",
+                "/v1/legacy/ops/bastion",
+                200,
+                r#"{"data":{"key":"KEY"}}"#,
+            );
+
+        let client = VaultClient::connect(&config(&server)).await.unwrap();
+        for _ in 0..2 {
+            let secret = client
+                .get_secret(&SecretId::new("legacy:ops/bastion#key"))
+                .await
+                .unwrap();
+            assert_eq!(*secret.value, "KEY");
+        }
+
+        assert_eq!(
+            server
+                .requests_to("/v1/sys/internal/ui/mounts/legacy")
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_mount_lookup_is_not_cached() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/v1/auth/token/lookup-self", 200, r#"{"data":{}}"#)
+            .respond(
+                "GET",
+                "/v1/sys/internal/ui/mounts/legacy",
+                503,
+                r#"{"errors":[]}"#,
+            )
+            .respond("GET", "/v1/sys/internal/ui/mounts/legacy", 200, KV1_MOUNT)
+            .respond(
+                "GET",
+                "/v1/legacy/ops/bastion",
+                200,
+                r#"{"data":{"key":"KEY"}}"#,
+            );
+
+        let client = VaultClient::connect(&config(&server)).await.unwrap();
+        let reference = SecretId::new("legacy:ops/bastion#key");
+        // Read as KV version 2 while the mount cannot be looked up
+        assert!(client.get_secret(&reference).await.is_err());
+        let secret = client.get_secret(&reference).await.unwrap();
+
+        assert_eq!(*secret.value, "KEY");
+        assert_eq!(
+            server
+                .requests_to("/v1/sys/internal/ui/mounts/legacy")
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn approle_logs_in_again_when_the_token_expires() {
+        let server = StubServer::start().await;
+        server
+            .respond(
+                "POST",
+                "/v1/auth/approle/login",
+                200,
+                r#"{"auth":{"client_token":"first-token"}}"#,
+            )
+            .respond(
+                "POST",
+                "/v1/auth/approle/login",
+                200,
+                r#"{"auth":{"client_token":"second-token"}}"#,
+            )
+            .respond(
+                "GET",
+                "/v1/secret/data/ssh/deploy",
+                403,
+                r#"{"errors":["permission denied"]}"#,
+            )
+            .respond(
+                "GET",
+                "/v1/secret/data/ssh/deploy",
+                200,
+                r#"{"data":{"data":{"private_key":"KEY"}}}"#,
+            );
+        let config = VaultConfig {
+            address: Some(server.address.clone()),
+            approle: Some(AppRole {
+                role_id: "role".to_string(),
+                secret_id: Zeroizing::new("secret".to_string()),
+                mount: "approle".to_string(),
+            }),
+            kv_version: Some(2),
+            ..VaultConfig::default()
+        };
+
+        let client = VaultClient::connect(&config).await.unwrap();
+        let secret = client
+            .get_secret(&SecretId::new("ssh/deploy"))
+            .await
+            .unwrap();
+
+        assert_eq!(*secret.value, "KEY");
+        let logins = server.requests_to("/v1/auth/approle/login");
+        assert_eq!(logins.len(), 2);
+        assert!(logins[0].body.contains(r#""role_id":"role""#));
+        let reads = server.requests_to("/v1/secret/data/ssh/deploy");
+        assert_eq!(reads[0].headers["x-vault-token"], "first-token");
+        assert_eq!(reads[1].headers["x-vault-token"], "second-token");
+    }
+
+    #[tokio::test]
+    async fn missing_secret_and_field_are_reported() {
+        let server = StubServer::start().await;
+        server
+            .respond("GET", "/v1/auth/token/lookup-self", 200, r#"{"data":{}}"#)
+            .respond(
+                "GET",
+                "/v1/secret/data/ssh/deploy",
+                200,
+                r#"{"data":{"data":{"public_key":"PUB","user":"deploy"}}}"#,
+            );
+        let config = VaultConfig {
+            kv_version: Some(2),
+            namespace: Some("team".to_string()),
+            ..config(&server)
+        };
+
+        let client = VaultClient::connect(&config).await.unwrap();
+        let missing = client
+            .get_secret(&SecretId::new("ssh/gone"))
+            .await
+            .err()
+            .expect("an unknown secret should fail");
+        let no_field = client
+            .get_secret(&SecretId::new("ssh/deploy"))
+            .await
+            .err()
+            .expect("a secret without the field should fail");
+
+        assert!(missing.to_string().contains("not found"));
+        assert!(no_field
+            .to_string()
+            .contains("available fields: public_key, user"));
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.headers["x-vault-namespace"] == "team"));
+    }
+
+    #[tokio::test]
+    async fn refused_token_fails_to_connect() {
+        let server = StubServer::start().await;
+        server.respond(
+            "GET",
+            "/v1/auth/token/lookup-self",
+            403,
+            r#"{"errors":["permission denied"]}"#,
+        );
+
+        let error = VaultClient::connect(&config(&server))
+            .await
+            .err()
+            .expect("a refused token should fail");
+
+        assert!(error.to_string().contains("Authentication failed"));
+        assert!(error.to_string().contains("permission denied"));
+    }
+}
@@ -0,0 +1,482 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+use crate::bitwarden::agent::{SecretData, SecretFetcher, SecretId};
+use crate::config::Config;
+
+/// Seconds a request to Vault may take before it is abandoned
+const REQUEST_TIMEOUT: u64 = 30;
+
+/// Settings of the `vault` backend, also used for OpenBao
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+    /// Server address, e.g. `https://vault.example.com:8200`, `VAULT_ADDR` overrides it
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Token used to read the secrets, `VAULT_TOKEN` overrides it
+    #[serde(default)]
+    pub token: Option<Zeroizing<String>>,
+    /// AppRole credentials, logged in with instead of a token when set
+    #[serde(default)]
+    pub approle: Option<AppRole>,
+    /// Enterprise namespace, `VAULT_NAMESPACE` overrides it
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// KV mount of secrets referenced without one
+    #[serde(default = "default_mount")]
+    pub mount: String,
+    /// Field holding the key in secrets referenced without one
+    #[serde(default = "default_field")]
+    pub field: String,
+    /// KV engine version of every mount (1 or 2), detected per mount if unset
+    #[serde(default)]
+    pub kv_version: Option<u8>,
+}
+
+/// AppRole login, see https://developer.hashicorp.com/vault/docs/auth/approle
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppRole {
+    pub role_id: String,
+    pub secret_id: Zeroizing<String>,
+    /// Path the AppRole auth method is enabled at
+    #[serde(default = "default_approle_mount")]
+    pub mount: String,
+}
+
+fn default_mount() -> String {
+    "secret".to_string()
+}
+
+fn default_field() -> String {
+    "private_key".to_string()
+}
+
+fn default_approle_mount() -> String {
+    "approle".to_string()
+}
+
+impl Default for VaultConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            token: None,
+            approle: None,
+            namespace: None,
+            mount: default_mount(),
+            field: default_field(),
+            kv_version: None,
+        }
+    }
+}
+
+impl VaultConfig {
+    /// Settings with the `VAULT_*` environment variables applied, as the `vault` CLI does
+    pub fn with_environment(&self) -> Self {
+        let mut config = self.clone();
+        if let Ok(address) = std::env::var("VAULT_ADDR") {
+            config.address = Some(address);
+        }
+        if let Ok(token) = std::env::var("VAULT_TOKEN") {
+            config.token = Some(Zeroizing::new(token));
+        }
+        if let Ok(namespace) = std::env::var("VAULT_NAMESPACE") {
+            config.namespace = Some(namespace);
+        }
+        config
+    }
+}
+
+/// Location of a key: `[<mount>:]<path>[#<field>]`, e.g. `ssh/deploy` or `kv:ops/bastion#key`
+#[derive(Debug, PartialEq, Eq)]
+pub struct SecretRef {
+    pub mount: String,
+    pub path: String,
+    pub field: String,
+}
+
+impl SecretRef {
+    pub fn parse(reference: &str, config: &VaultConfig) -> Result<Self> {
+        let (location, field) = match reference.split_once('#') {
+            Some((location, field)) => (location, field),
+            None => (reference, config.field.as_str()),
+        };
+        let (mount, path) = match location.split_once(':') {
+            Some((mount, path)) => (mount, path),
+            None => (config.mount.as_str(), location),
+        };
+        let mount = mount.trim_matches('/');
+        let path = path.trim_matches('/');
+        if mount.is_empty() || path.is_empty() || field.is_empty() {
+            bail!(
+                "Invalid Vault secret reference '{}', expected [<mount>:]<path>[#<field>]",
+                reference
+            );
+        }
+        Ok(Self {
+            mount: mount.to_string(),
+            path: path.to_string(),
+            field: field.to_string(),
+        })
+    }
+}
+
+/// Check the `vault` section of the configuration
+pub fn validate(config: &Config) -> Result<()> {
+    let vault = &config.vault;
+    if let Some(version) = vault.kv_version {
+        if version != 1 && version != 2 {
+            bail!("Invalid vault.kv_version {}: use 1 or 2", version);
+        }
+    }
+    Ok(())
+}
+
+/// Address of the configured server, for diagnostics
+pub fn address(config: &Config) -> String {
+    config
+        .vault
+        .with_environment()
+        .address
+        .unwrap_or_else(|| "Vault (address not set)".to_string())
+}
+
+/// Authenticate to Vault with the configured token or AppRole
+pub async fn connect(config: &Config) -> Result<VaultClient> {
+    VaultClient::connect(&config.vault.with_environment()).await
+}
+
+/// Fields of a KV secret, only text fields can hold a key
+type Fields = HashMap<String, Field>;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Field {
+    Text(Zeroizing<String>),
+    Other(IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct KvV2 {
+    data: Fields,
+}
+
+#[derive(Deserialize)]
+struct MountInfo {
+    #[serde(default)]
+    options: Option<MountOptions>,
+}
+
+#[derive(Deserialize)]
+struct MountOptions {
+    #[serde(default)]
+    version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    auth: LoginAuth,
+}
+
+#[derive(Deserialize)]
+struct LoginAuth {
+    client_token: Zeroizing<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+/// Client of the KV engines of a Vault or OpenBao server
+#[derive(Clone)]
+pub struct VaultClient {
+    http: reqwest::Client,
+    address: String,
+    namespace: Option<String>,
+    approle: Option<AppRole>,
+    token: Arc<Mutex<Zeroizing<String>>>,
+    /// Secret references are resolved against these defaults
+    defaults: VaultConfig,
+    /// KV version of each mount already resolved
+    kv_versions: Arc<Mutex<HashMap<String, u8>>>,
+}
+
+impl VaultClient {
+    pub async fn connect(config: &VaultConfig) -> Result<Self> {
+        let address = config
+            .address
+            .as_ref()
+            .context("Vault address is not set, add vault.address to the config or set VAULT_ADDR")?
+            .trim_end_matches('/')
+            .to_string();
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .user_agent(format!("vault-conductor/{}", env!("CARGO_PKG_VERSION")))
+            .build()
+            .context("Failed to create the Vault HTTP client")?;
+        let client = Self {
+            http,
+            address,
+            namespace: config.namespace.clone(),
+            approle: config.approle.clone(),
+            token: Arc::new(Mutex::new(Zeroizing::new(String::new()))),
+            defaults: config.clone(),
+            kv_versions: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        match (&config.approle, &config.token) {
+            (Some(_), _) => client.login().await?,
+            (None, Some(token)) => {
+                *client.token.lock().unwrap() = token.clone();
+                // Fail at startup rather than on the first signature
+                let response = client
+                    .send(Method::GET, "auth/token/lookup-self")
+                    .await
+                    .context("Vault: Failed to reach the server")?;
+                check_status(response, "Vault: Authentication failed, check the token").await?;
+            }
+            (None, None) => bail!(
+                "Vault credentials are not set, add vault.token or vault.approle \
+                to the config or set VAULT_TOKEN"
+            ),
+        }
+        info!("Authenticated to Vault at {}", client.address);
+        Ok(client)
+    }
+
+    /// Log in with the AppRole credentials and keep the token they grant
+    async fn login(&self) -> Result<()> {
+        let approle = self
+            .approle
+            .as_ref()
+            .context("Vault: No AppRole configured to log in with")?;
+        let body = serde_json::json!({
+            "role_id": approle.role_id,
+            "secret_id": approle.secret_id.as_str(),
+        });
+        let response = self
+            .request(Method::POST, &format!("auth/{}/login", approle.mount))
+            .json(&body)
+            .send()
+            .await
+            .context("Vault: Failed to reach the server")?;
+        let login: Login = read_json(
+            check_status(
+                response,
+                "Vault: AppRole login failed, check role_id and secret_id",
+            )
+            .await?,
+        )
+        .await?;
+        *self.token.lock().unwrap() = login.auth.client_token;
+        debug!("Logged in to Vault with AppRole {}", approle.role_id);
+        Ok(())
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let mut request = self
+            .http
+            .request(method, format!("{}/v1/{}", self.address, path));
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        request
+    }
+
+    /// Send an authenticated request
+    async fn send(&self, method: Method, path: &str) -> reqwest::Result<reqwest::Response> {
+        let token = self.token.lock().unwrap().clone();
+        self.request(method, path)
+            .header("X-Vault-Token", token.as_str())
+            .send()
+            .await
+    }
+
+    /// Send an authenticated request, logging in again once if the AppRole token expired
+    async fn send_with_login(&self, method: Method, path: &str) -> Result<reqwest::Response> {
+        let response = self
+            .send(method.clone(), path)
+            .await
+            .context("Vault: Failed to reach the server")?;
+        if response.status() != StatusCode::FORBIDDEN || self.approle.is_none() {
+            return Ok(response);
+        }
+        info!("Vault token refused, logging in with AppRole again");
+        self.login().await?;
+        self.send(method, path)
+            .await
+            .context("Vault: Failed to reach the server")
+    }
+
+    /// KV engine version of `mount`, as configured or as reported by the server
+    async fn kv_version(&self, mount: &str) -> u8 {
+        if let Some(version) = self.defaults.kv_version {
+            return version;
+        }
+        if let Some(version) = self.kv_versions.lock().unwrap().get(mount) {
+            return *version;
+        }
+        // Only a version reported by the server is cached, a failed lookup (e.g. while
+        // offline) falls back to version 2 for this request only
+        let path = format!("sys/internal/ui/mounts/{}", mount);
+        let reported = match self.send_with_login(Method::GET, &path).await {
+            Ok(response) if response.status().is_success() => {
+                match read_json::<Envelope<MountInfo>>(response).await {
+                    Ok(info) => {
+                        let options = info.data.options;
+                        match options.and_then(|options| options.version).as_deref() {
+                            Some("2") => Some(2),
+                            _ => Some(1),
+                        }
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Vault: Unreadable details of mount '{}', assuming KV version 2: {:#}",
+                            mount, e
+                        );
+                        None
+                    }
+                }
+            }
+            Ok(response) => {
+                warn!(
+                    "Vault: Cannot read details of mount '{}' ({}), assuming KV version 2",
+                    mount,
+                    response.status()
+                );
+                None
+            }
+            Err(e) => {
+                warn!("Vault: {:#}, assuming KV version 2", e);
+                None
+            }
+        };
+        let Some(version) = reported else {
+            return 2;
+        };
+        debug!("Vault mount '{}' is a KV version {} engine", mount, version);
+        self.kv_versions
+            .lock()
+            .unwrap()
+            .insert(mount.to_string(), version);
+        version
+    }
+}
+
+#[async_trait::async_trait]
+impl SecretFetcher for VaultClient {
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData> {
+        let secret = SecretRef::parse(id.as_str(), &self.defaults)?;
+        let version = self.kv_version(&secret.mount).await;
+        let path = match version {
+            2 => format!("{}/data/{}", secret.mount, secret.path),
+            _ => format!("{}/{}", secret.mount, secret.path),
+        };
+
+        let response = self.send_with_login(Method::GET, &path).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!(
+                "Vault: Secret '{}' not found in mount '{}' (404). \
+                Please verify the path and that it has not been deleted",
+                secret.path,
+                secret.mount
+            );
+        }
+        let response = check_status(
+            response,
+            &format!("Vault: Failed to read secret '{}'", secret.path),
+        )
+        .await?;
+        let mut fields = match version {
+            2 => read_json::<Envelope<KvV2>>(response).await?.data.data,
+            _ => read_json::<Envelope<Fields>>(response).await?.data,
+        };
+
+        let value = match fields.remove(&secret.field) {
+            Some(Field::Text(value)) => value,
+            Some(Field::Other(_)) => bail!(
+                "Vault: Field '{}' of secret '{}' is not a string",
+                secret.field,
+                secret.path
+            ),
+            None => {
+                let mut names: Vec<&String> = fields.keys().collect();
+                names.sort();
+                bail!(
+                    "Vault: Field '{}' not found in secret '{}', available fields: {}",
+                    secret.field,
+                    secret.path,
+                    names
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        };
+        // An optional `note` field may carry a `[vault-conductor]` option block
+        let note = match fields.remove("note") {
+            Some(Field::Text(note)) => note.to_string(),
+            _ => String::new(),
+        };
+        Ok(SecretData {
+            name: secret.path,
+            value,
+            note,
+            project: Some(secret.mount),
+        })
+    }
+}
+
+/// Turn an error status into an error carrying the messages returned by Vault
+async fn check_status(response: reqwest::Response, what: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let errors = response
+        .json::<ErrorBody>()
+        .await
+        .map(|body| body.errors.join(", "))
+        .unwrap_or_default();
+    let detail = if errors.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", errors)
+    };
+    match status {
+        StatusCode::FORBIDDEN => Err(anyhow!(
+            "{} (403){}. Please check the policies attached to the token",
+            what,
+            detail
+        )),
+        _ => Err(anyhow!("{} ({}){}", what, status, detail)),
+    }
+}
+
+/// Parse a response body, which may hold key material and is wiped once parsed
+async fn read_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let body = Zeroizing::new(
+        response
+            .bytes()
+            .await
+            .context("Vault: Failed to read the response")?
+            .to_vec(),
+    );
+    serde_json::from_slice(&body).context("Vault: Unexpected response format")
+}
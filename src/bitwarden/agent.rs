@@ -1,138 +1,1083 @@
-use anyhow::Result;
+use crate::bitwarden::comment::{fallback_comment, render_comment, CommentFields};
+use crate::bitwarden::confirm::confirm_key_use;
+use crate::bitwarden::connection::ConnectionContext;
+use crate::bitwarden::keyring::{
+    parse_note_options, parse_secret_value, KeyConstraints, KeyEntry, NoteOptions,
+};
+use crate::config::{KeyOptions, KeyPolicy};
+use crate::secure_memory::{KeyShield, ShieldedSecret};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use log::{debug, warn};
+use log::{debug, error, info, warn};
+use sha2::{Digest, Sha512};
+use signature::SignatureEncoding;
 use signature::Signer;
 use ssh_agent_lib::agent::Session;
 use ssh_agent_lib::error::AgentError;
 use ssh_agent_lib::proto::{Extension, Identity, PublicCredential, SignRequest};
-use ssh_key::{PrivateKey, Signature};
+use ssh_key::public::KeyData;
+use ssh_key::{
+    Algorithm, Certificate, Fingerprint, HashAlg, Mpint, PrivateKey, PublicKey, Signature,
+};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 /// Struct that holds both secret key and value
 pub struct SecretData {
     pub name: String,
-    pub value: String,
+    pub value: Zeroizing<String>,
+    /// Free text note of the secret, may carry a `[vault-conductor]` option block
+    pub note: String,
+    /// Name of the project the secret belongs to, if any
+    pub project: Option<String>,
+}
+
+/// Reference to a secret in the configured backend, e.g. a Bitwarden secret UUID
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SecretId(String);
+
+impl SecretId {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SecretId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Uuid> for SecretId {
+    fn from(id: Uuid) -> Self {
+        Self(id.to_string())
+    }
 }
 
 // 1. Define a trait for fetching secrets
 #[async_trait]
 pub trait SecretFetcher: Send + Sync + 'static {
-    async fn get_secret(&self, id: Uuid) -> Result<SecretData>;
+    async fn get_secret(&self, id: &SecretId) -> Result<SecretData>;
+
+    /// Secrets holding keys that the backend finds by itself, e.g. in the configured
+    /// Bitwarden projects, served next to the configured ones
+    async fn discover_secrets(&self) -> Result<Vec<SecretId>> {
+        Ok(Vec::new())
+    }
+
+    /// Secrets designated by `references` that the backend resolves, e.g. Bitwarden
+    /// secret names or patterns. References missing from the result are kept as is.
+    async fn resolve_references(
+        &self,
+        _references: &[String],
+    ) -> Result<HashMap<String, Vec<SecretId>>> {
+        Ok(HashMap::new())
+    }
+}
+
+/// Outcome of loading a configured key, for status reports
+pub struct KeyCheck {
+    pub secret_id: SecretId,
+    pub name: Option<String>,
+    pub result: Result<Vec<PublicKey>, String>,
+    /// Keys of the secret refused by the key policy, the other keys are still served
+    pub rejected: Vec<RejectedKey>,
+}
+
+/// Key of a secret refused by the key policy
+#[derive(Clone)]
+pub struct RejectedKey {
+    pub public_key: PublicKey,
+    pub reason: String,
+}
+
+/// Keys parsed from a secret, before they are cached
+struct LoadedSecret {
+    keys: Vec<CachedKey>,
+    name: String,
+    rejected: Vec<RejectedKey>,
+}
+
+/// Public part of a cached key, what is advertised to clients
+#[derive(Clone)]
+struct KeyIdentity {
+    public_key: PublicKey,
+    certificate: Option<Certificate>,
+    comment: String,
+    constraints: KeyConstraints,
+    /// Not advertised, signs only when a client asks for it
+    hidden: bool,
+}
+
+impl KeyIdentity {
+    /// Resolve the options of a key: local config first, then the secret note,
+    /// then the key bundle entry. `default_comment` is used when none sets a comment.
+    fn resolve(
+        entry: &KeyEntry,
+        local: &KeyOptions,
+        note: &NoteOptions,
+        default_comment: String,
+    ) -> Self {
+        Self {
+            public_key: entry.private_key.public_key().clone(),
+            certificate: entry.certificate.clone(),
+            comment: local
+                .comment
+                .clone()
+                .or_else(|| note.comment.clone())
+                .or_else(|| entry.comment.clone())
+                .unwrap_or(default_comment),
+            constraints: KeyConstraints {
+                lifetime: local
+                    .lifetime
+                    .or(note.lifetime)
+                    .or(entry.constraints.lifetime),
+                confirm: local
+                    .confirm
+                    .or(note.confirm)
+                    .unwrap_or(entry.constraints.confirm),
+                namespaces: local
+                    .namespaces
+                    .clone()
+                    .or_else(|| note.namespaces.clone())
+                    .or_else(|| entry.constraints.namespaces.clone()),
+            },
+            hidden: local.hidden.or(note.hidden).unwrap_or(false),
+        }
+    }
+}
+
+/// Private key kept encrypted in memory, only decrypted while in use
+struct CachedKey {
+    /// OpenSSH binary encoding of the private key, shielded
+    secret: ShieldedSecret,
+    identity: KeyIdentity,
+    loaded_at: Instant,
+}
+
+impl CachedKey {
+    /// Whether the key has been in memory longer than its `lifetime` constraint
+    fn is_expired(&self) -> bool {
+        self.identity
+            .constraints
+            .lifetime
+            .is_some_and(|lifetime| self.loaded_at.elapsed() >= Duration::from_secs(lifetime))
+    }
+}
+
+/// Constraints of a key stored in several secrets, the most restrictive of each:
+/// the shortest lifetime, a confirmation if any asks for one, and the namespaces
+/// allowed by all of them
+fn merge_constraints<'a>(constraints: impl Iterator<Item = &'a KeyConstraints>) -> KeyConstraints {
+    constraints.fold(KeyConstraints::default(), |merged, constraints| {
+        KeyConstraints {
+            lifetime: match (merged.lifetime, constraints.lifetime) {
+                (Some(merged), Some(lifetime)) => Some(merged.min(lifetime)),
+                (merged, lifetime) => merged.or(lifetime),
+            },
+            confirm: merged.confirm || constraints.confirm,
+            namespaces: match (merged.namespaces, &constraints.namespaces) {
+                (Some(merged), Some(namespaces)) => Some(
+                    merged
+                        .into_iter()
+                        .filter(|namespace| namespaces.contains(namespace))
+                        .collect(),
+                ),
+                (merged, namespaces) => merged.or_else(|| namespaces.clone()),
+            },
+        }
+    })
+}
+
+/// SHA256 fingerprints of `keys`, to tell whether a secret was rotated
+fn fingerprints(keys: &[CachedKey]) -> Vec<String> {
+    keys.iter()
+        .map(|key| {
+            key.identity
+                .public_key
+                .fingerprint(HashAlg::Sha256)
+                .to_string()
+        })
+        .collect()
+}
+
+/// Signatures produced by a key, to enforce `max_signatures`
+#[derive(Default)]
+struct SignatureCounter {
+    count: u64,
+    window_start: Option<Instant>,
+}
+
+impl SignatureCounter {
+    /// Reset the counter if its window has elapsed
+    fn expire_window(&mut self, window: Option<u64>) {
+        if let (Some(window), Some(start)) = (window, self.window_start) {
+            if start.elapsed() >= Duration::from_secs(window) {
+                *self = Self::default();
+            }
+        }
+    }
 }
 
 // 2. The Agent logic now relies on the trait, not the concrete Client
 #[derive(Clone)]
 pub struct BitwardenAgent<F: SecretFetcher + Clone> {
     fetcher: Arc<F>,
-    secret_ids: Vec<Uuid>,
-    cached_keys: Arc<Mutex<Vec<Option<PrivateKey>>>>,
+    /// Secrets known to the agent, secrets added later are appended
+    secret_ids: Arc<Mutex<Vec<SecretId>>>,
+    /// Indices in `secret_ids` of the secrets served by this view of the agent
+    exposed: Arc<Mutex<Vec<usize>>>,
+    shield: Arc<KeyShield>,
+    /// Keys loaded from each secret, a secret may hold several of them
+    cached_keys: Arc<Mutex<Vec<Option<Vec<CachedKey>>>>>,
     cached_key_names: Arc<Mutex<Vec<Option<String>>>>,
+    /// Keys of each secret refused by the key policy, for status reports
+    rejected_keys: Arc<Mutex<Vec<Vec<RejectedKey>>>>,
+    /// Secrets each loaded key was found in, a key stored in several secrets is advertised once
+    key_sources: Arc<Mutex<HashMap<KeyData, BTreeSet<usize>>>>,
+    key_options: Arc<HashMap<SecretId, KeyOptions>>,
+    key_policy: Arc<KeyPolicy>,
+    comment_template: Arc<Option<String>>,
+    signature_counters: Arc<Mutex<Vec<SignatureCounter>>>,
+    /// Digest of the passphrase the agent was locked with, if locked
+    lock_digest: Arc<Mutex<Option<Zeroizing<[u8; 64]>>>>,
+    /// Client served by this clone of the agent
+    connection: ConnectionContext,
 }
 
 impl<F: SecretFetcher + Clone> BitwardenAgent<F> {
-    pub fn new(fetcher: Arc<F>, secret_ids: Vec<Uuid>) -> Self {
+    pub fn new(fetcher: Arc<F>, secret_ids: Vec<SecretId>) -> Self {
         let count = secret_ids.len();
         Self {
             fetcher,
-            secret_ids,
-            cached_keys: Arc::new(Mutex::new(vec![None; count])),
+            secret_ids: Arc::new(Mutex::new(secret_ids)),
+            exposed: Arc::new(Mutex::new((0..count).collect())),
+            shield: Arc::new(KeyShield::new()),
+            cached_keys: Arc::new(Mutex::new((0..count).map(|_| None).collect())),
             cached_key_names: Arc::new(Mutex::new(vec![None; count])),
+            rejected_keys: Arc::new(Mutex::new(vec![Vec::new(); count])),
+            key_sources: Arc::new(Mutex::new(HashMap::new())),
+            key_options: Arc::new(HashMap::new()),
+            key_policy: Arc::new(KeyPolicy::default()),
+            comment_template: Arc::new(None),
+            signature_counters: Arc::new(Mutex::new(
+                (0..count).map(|_| SignatureCounter::default()).collect(),
+            )),
+            lock_digest: Arc::new(Mutex::new(None)),
+            connection: ConnectionContext::default(),
         }
     }
 
-    async fn get_private_key(&self, index: usize) -> Result<PrivateKey, AgentError> {
+    /// Apply per-key options, keys without an entry use the defaults
+    pub fn with_key_options(mut self, options: &HashMap<SecretId, KeyOptions>) -> Self {
+        self.key_options = Arc::new(options.clone());
+        self
+    }
+
+    /// Apply a key strength and algorithm policy to every key
+    pub fn with_key_policy(mut self, policy: KeyPolicy) -> Self {
+        self.key_policy = Arc::new(policy);
+        self
+    }
+
+    /// Render advertised comments with a template, see `comment_template` in the config
+    pub fn with_comment_template(mut self, template: Option<String>) -> Self {
+        self.comment_template = Arc::new(template);
+        self
+    }
+
+    /// Tag the log lines and confirmation prompts of this session with its client
+    pub fn with_connection(mut self, connection: ConnectionContext) -> Self {
+        self.connection = connection;
+        self
+    }
+
+    /// Client served by this session
+    pub fn connection(&self) -> &ConnectionContext {
+        &self.connection
+    }
+
+    /// Agent serving only `secret_ids`, e.g. on the socket of a profile.
+    /// Views share the fetcher, the key cache and the signature counters,
+    /// each one is locked and unlocked on its own.
+    pub fn view(&self, secret_ids: &[SecretId]) -> Result<Self> {
+        let known = self.secret_ids.lock().unwrap();
+        let exposed = secret_ids
+            .iter()
+            .map(|id| {
+                known
+                    .iter()
+                    .position(|known| known == id)
+                    .ok_or_else(|| anyhow!("Secret ID {} is not served by the agent", id))
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        Ok(Self {
+            exposed: Arc::new(Mutex::new(exposed)),
+            lock_digest: Arc::new(Mutex::new(None)),
+            ..self.clone()
+        })
+    }
+
+    /// ID of the secret at `index`
+    fn secret_id(&self, index: usize) -> SecretId {
+        self.secret_ids.lock().unwrap()[index].clone()
+    }
+
+    /// Indices of the secrets served by this view
+    fn exposed(&self) -> Vec<usize> {
+        self.exposed.lock().unwrap().clone()
+    }
+
+    /// Options of the secret at `index`, the defaults without an entry
+    fn key_options(&self, index: usize) -> KeyOptions {
+        self.key_options
+            .get(&self.secret_id(index))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Make sure the keys of the secret at `index` are cached and return their public part
+    async fn get_identities(&self, index: usize) -> Result<Vec<KeyIdentity>, AgentError> {
         // Check Cache
         {
-            let cache = self.cached_keys.lock().unwrap();
-            if let Some(Some(key)) = cache.get(index) {
-                return Ok(key.clone());
+            let mut cache = self.cached_keys.lock().unwrap();
+            if let Some(slot) = cache.get_mut(index) {
+                if let Some(keys) = slot.as_ref() {
+                    if !keys.iter().any(CachedKey::is_expired) {
+                        return Ok(keys.iter().map(|key| key.identity.clone()).collect());
+                    }
+                    debug!(
+                        "Keys of secret {} reached their lifetime, fetching again",
+                        index
+                    );
+                    slot.take();
+                }
             }
         }
 
+        let loaded = self.load_keys(index).await?;
+        Ok(self.store_keys(index, loaded))
+    }
+
+    /// Fetch and parse the keys of the secret at `index`, with the name of the secret
+    async fn load_keys(&self, index: usize) -> Result<LoadedSecret, AgentError> {
         // Get the secret ID for this index
-        let secret_id = self.secret_ids.get(index).ok_or_else(|| {
-            AgentError::other(Box::new(std::io::Error::other("Invalid key index")))
-        })?;
+        let secret_id = self
+            .secret_ids
+            .lock()
+            .unwrap()
+            .get(index)
+            .cloned()
+            .ok_or_else(|| {
+                AgentError::other(Box::new(std::io::Error::other("Invalid key index")))
+            })?;
 
         // Fetch via Trait (gets both key and value in one call)
         let secret_data = self
             .fetcher
-            .get_secret(*secret_id)
+            .get_secret(&secret_id)
             .await
             .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))?;
 
-        // Parse
-        let key = PrivateKey::from_openssh(&secret_data.value)
-            .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))?;
+        // Parse, then keep only the shielded binary encoding of each key
+        let entries = parse_secret_value(secret_data.value.as_str())
+            .map_err(|e| AgentError::other(Box::new(std::io::Error::other(format!("{:#}", e)))))?;
+        let note_options = parse_note_options(&secret_data.note).map_err(|e| {
+            AgentError::other(Box::new(std::io::Error::other(format!(
+                "Secret '{}': {}",
+                secret_data.name, e
+            ))))
+        })?;
+        let local_options = self.key_options(index);
+        let entries = self.keep_pinned_key(index, entries)?;
+        let mut keys = Vec::with_capacity(entries.len());
+        let mut rejected = Vec::new();
+        for entry in entries {
+            let default_comment =
+                self.default_comment(index, &secret_data, entry.private_key.public_key());
+            let identity =
+                KeyIdentity::resolve(&entry, &local_options, &note_options, default_comment);
+            let public_key = identity.public_key.clone();
+            // A key rejected by the policy is skipped, the other keys of a bundle are still served
+            if let Err(e) = self.key_policy.check_key(&public_key) {
+                error!(
+                    "Key {} of secret '{}' rejected by key policy, skipping it: {}",
+                    public_key.fingerprint(HashAlg::Sha256),
+                    secret_data.name,
+                    e
+                );
+                rejected.push(RejectedKey {
+                    public_key,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+            let encoded = entry
+                .private_key
+                .to_bytes()
+                .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))?;
+            let secret = self
+                .shield
+                .shield(&encoded)
+                .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))?;
+            keys.push(CachedKey {
+                secret,
+                identity,
+                loaded_at: Instant::now(),
+            });
+        }
+        if let (true, Some(first)) = (keys.is_empty(), rejected.first()) {
+            return Err(AgentError::other(Box::new(std::io::Error::other(format!(
+                "Key '{}' rejected by key policy: {}",
+                secret_data.name, first.reason
+            )))));
+        }
+        Ok(LoadedSecret {
+            keys,
+            name: secret_data.name,
+            rejected,
+        })
+    }
+
+    /// Cache the keys of the secret at `index` in place of the previous ones, which are
+    /// wiped on drop, and return their public part
+    fn store_keys(&self, index: usize, loaded: LoadedSecret) -> Vec<KeyIdentity> {
+        let identities: Vec<KeyIdentity> =
+            loaded.keys.iter().map(|key| key.identity.clone()).collect();
+        self.record_key_sources(index, &identities);
 
-        // Update both caches
+        // Update the caches, replaced entries are wiped on drop
         let mut key_cache = self.cached_keys.lock().unwrap();
         if let Some(slot) = key_cache.get_mut(index) {
-            *slot = Some(key.clone());
+            *slot = Some(loaded.keys);
+        }
+
+        self.store_details(index, loaded.name, loaded.rejected);
+        identities
+    }
+
+    /// Remember the name of the secret at `index` and its keys refused by the key policy
+    fn store_details(&self, index: usize, name: String, rejected: Vec<RejectedKey>) {
+        if let Some(slot) = self.cached_key_names.lock().unwrap().get_mut(index) {
+            *slot = Some(name);
         }
+        if let Some(slot) = self.rejected_keys.lock().unwrap().get_mut(index) {
+            *slot = rejected;
+        }
+    }
+
+    /// Remember which secrets hold each key, warning when a key is stored in several secrets
+    fn record_key_sources(&self, index: usize, identities: &[KeyIdentity]) {
+        let mut sources = self.key_sources.lock().unwrap();
+        for secrets in sources.values_mut() {
+            secrets.remove(&index);
+        }
+        sources.retain(|_, secrets| !secrets.is_empty());
 
-        let mut name_cache = self.cached_key_names.lock().unwrap();
-        if let Some(slot) = name_cache.get_mut(index) {
-            *slot = Some(secret_data.name);
+        for identity in identities {
+            let secrets = sources
+                .entry(identity.public_key.key_data().clone())
+                .or_default();
+            secrets.insert(index);
+            if secrets.len() > 1 {
+                warn!(
+                    "Key {} is stored in several secrets ({}), advertising it once",
+                    identity.public_key.fingerprint(HashAlg::Sha256),
+                    secrets
+                        .iter()
+                        .map(|&other| self.secret_id(other).to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
         }
+    }
 
-        Ok(key)
+    /// Keys last loaded from the secret at `index`, even if no longer cached
+    fn known_keys(&self, index: usize) -> Vec<KeyData> {
+        let sources = self.key_sources.lock().unwrap();
+        sources
+            .iter()
+            .filter(|(_, secrets)| secrets.contains(&index))
+            .map(|(key, _)| key.clone())
+            .collect()
     }
 
-    fn get_cached_key_name(&self, index: usize) -> String {
-        let cache = self.cached_key_names.lock().unwrap();
-        // write a placeholder key name to be shown as key comment
-        cache
-            .get(index)
-            .and_then(|opt| opt.clone())
-            .unwrap_or_else(|| "bitwarden-sdk-key".to_string())
+    /// Secrets among `unavailable` known to hold `key`
+    fn unavailable_sources(&self, key: &KeyData, unavailable: &[usize]) -> Vec<SecretId> {
+        let sources = self.key_sources.lock().unwrap();
+        sources
+            .get(key)
+            .map(|secrets| {
+                unavailable
+                    .iter()
+                    .filter(|index| secrets.contains(index))
+                    .map(|&index| self.secret_id(index))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Keep only the key of the secret at `index` matching its pinned fingerprint, if
+    /// any. The secret is refused if it does not hold the pinned key, the other keys of
+    /// a bundle are never served: they may have been added by someone able to write it.
+    fn keep_pinned_key(
+        &self,
+        index: usize,
+        entries: Vec<KeyEntry>,
+    ) -> Result<Vec<KeyEntry>, AgentError> {
+        let Some(pinned) = self.key_options(index).fingerprint else {
+            return Ok(entries);
+        };
+        let expected: Fingerprint = pinned.parse().map_err(|e: ssh_key::Error| {
+            AgentError::other(Box::new(std::io::Error::other(e.to_string())))
+        })?;
+
+        let (pinned, others): (Vec<KeyEntry>, Vec<KeyEntry>) =
+            entries.into_iter().partition(|entry| {
+                entry
+                    .private_key
+                    .public_key()
+                    .fingerprint(expected.algorithm())
+                    == expected
+            });
+        let others: Vec<String> = others
+            .iter()
+            .map(|entry| {
+                entry
+                    .private_key
+                    .public_key()
+                    .fingerprint(expected.algorithm())
+                    .to_string()
+            })
+            .collect();
+
+        if pinned.is_empty() {
+            let actual = others.join(", ");
+            error!(
+                "SECURITY WARNING: key fetched for secret ID {} has fingerprint {} \
+                but {} is pinned in the configuration. The secret may have been tampered with, \
+                refusing to load it.",
+                self.secret_id(index),
+                actual,
+                expected
+            );
+            return Err(AgentError::other(Box::new(std::io::Error::other(format!(
+                "Fingerprint mismatch: expected {}, got {}",
+                expected, actual
+            )))));
+        }
+        if !others.is_empty() {
+            error!(
+                "SECURITY WARNING: secret ID {} holds key(s) {} next to the pinned key {}. \
+                The secret may have been tampered with, only serving the pinned key.",
+                self.secret_id(index),
+                others.join(", "),
+                expected
+            );
+        }
+        Ok(pinned)
+    }
+
+    /// Sign `data` with the key at `position` of the secret at `index`, provided
+    /// it is still `expected`
+    async fn sign_with(
+        &self,
+        index: usize,
+        position: usize,
+        expected: &KeyData,
+        hash: Option<HashAlg>,
+        data: &[u8],
+    ) -> Result<Signature, AgentError> {
+        // Decrypt the private key only now, it is wiped when dropped
+        let key = self.get_private_key(index, position).await?;
+        // The key may have been rotated while waiting for a confirmation
+        if key.public_key().key_data() != expected {
+            return Err(AgentError::other(Box::new(std::io::Error::other(
+                "Key rotated while signing",
+            ))));
+        }
+
+        // For SSH agent protocol, we need to create a RAW signature (not OpenSSH format)
+        // using the underlying keypair's try_sign method
+        create_signature(&key, hash, data).map_err(|e| {
+            AgentError::other(Box::new(std::io::Error::other(format!(
+                "Signing failed: {}",
+                e
+            ))))
+        })
+    }
+
+    /// Decrypt the key at `position` of the secret at `index` for the duration
+    /// of a single operation
+    async fn get_private_key(
+        &self,
+        index: usize,
+        position: usize,
+    ) -> Result<PrivateKey, AgentError> {
+        self.get_identities(index).await?;
+
+        let decrypted = {
+            let cache = self.cached_keys.lock().unwrap();
+            let cached = cache
+                .get(index)
+                .and_then(|slot| slot.as_ref())
+                .and_then(|keys| keys.get(position))
+                .ok_or_else(|| {
+                    AgentError::other(Box::new(std::io::Error::other("Key evicted from cache")))
+                })?;
+            self.shield
+                .unshield(&cached.secret)
+                .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))?
+        };
+        PrivateKey::from_bytes(decrypted.as_bytes())
+            .map_err(|e| AgentError::other(Box::new(std::io::Error::other(e.to_string()))))
+    }
+
+    /// Comment of keys without an explicit one: the rendered `comment_template`,
+    /// else the secret name, else a placeholder derived from the secret ID
+    fn default_comment(&self, index: usize, secret: &SecretData, public_key: &PublicKey) -> String {
+        let secret_id = &self.secret_id(index);
+        match self.comment_template.as_deref() {
+            Some(template) => render_comment(
+                template,
+                &CommentFields {
+                    name: &secret.name,
+                    project: secret.project.as_deref(),
+                    secret_id,
+                    public_key,
+                },
+            ),
+            None if secret.name.is_empty() => fallback_comment(secret_id),
+            None => secret.name.clone(),
+        }
+    }
+
+    /// Load every configured key and report whether it can be served
+    pub async fn check_keys(&self) -> Vec<KeyCheck> {
+        let mut checks = Vec::new();
+        for index in self.exposed() {
+            let secret_id = &self.secret_id(index);
+            let result = self
+                .get_identities(index)
+                .await
+                .map(|identities| {
+                    identities
+                        .into_iter()
+                        .map(|identity| identity.public_key)
+                        .collect()
+                })
+                .map_err(|e| e.to_string());
+            let name = self.cached_key_names.lock().unwrap()[index].clone();
+            let rejected = self.rejected_keys.lock().unwrap()[index].clone();
+            checks.push(KeyCheck {
+                secret_id: secret_id.clone(),
+                name,
+                result,
+                rejected,
+            });
+        }
+        checks
+    }
+
+    /// Secrets served by this view
+    pub fn served_secrets(&self) -> Vec<SecretId> {
+        self.exposed()
+            .into_iter()
+            .map(|index| self.secret_id(index))
+            .collect()
+    }
+
+    /// Serve `secret_ids` on this view from now on, e.g. when secrets are added to or
+    /// removed from a watched project. Secrets the agent does not know yet are added,
+    /// the cached keys of the secrets no longer served are wiped.
+    pub fn serve_secrets(&self, secret_ids: &[SecretId]) {
+        let exposed: Vec<usize> = {
+            let mut known = self.secret_ids.lock().unwrap();
+            let exposed = secret_ids
+                .iter()
+                .map(|id| match known.iter().position(|known| known == id) {
+                    Some(index) => index,
+                    None => {
+                        known.push(id.clone());
+                        known.len() - 1
+                    }
+                })
+                .collect();
+            let count = known.len();
+            self.cached_keys.lock().unwrap().resize_with(count, || None);
+            self.cached_key_names.lock().unwrap().resize(count, None);
+            self.rejected_keys
+                .lock()
+                .unwrap()
+                .resize_with(count, Vec::new);
+            self.signature_counters
+                .lock()
+                .unwrap()
+                .resize_with(count, SignatureCounter::default);
+            exposed
+        };
+        let removed: Vec<usize> = self
+            .exposed()
+            .into_iter()
+            .filter(|index| !exposed.contains(index))
+            .collect();
+        *self.exposed.lock().unwrap() = exposed;
+        for index in removed {
+            self.evict(index);
+        }
+    }
+
+    /// Fetch the cached keys again and swap them with the fetched ones, e.g. to pick up
+    /// rotated keys. Keys failing to load stay cached as they were.
+    /// Returns the secrets whose keys changed.
+    pub async fn refresh(&self) -> Vec<SecretId> {
+        let loaded: Vec<usize> = self
+            .cached_keys
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_some())
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut rotated = Vec::new();
+        for index in loaded {
+            let secret_id = self.secret_id(index);
+            let LoadedSecret {
+                keys,
+                name,
+                rejected,
+            } = match self.load_keys(index).await {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    warn!(
+                        "Failed to refresh the keys of secret {}, keeping the cached ones: {}",
+                        secret_id, e
+                    );
+                    continue;
+                }
+            };
+            let identities: Vec<KeyIdentity> =
+                keys.iter().map(|key| key.identity.clone()).collect();
+            let current = fingerprints(&keys);
+            // Compared and replaced under the same guard, so that keys evicted
+            // meanwhile (e.g. the agent was locked) are not cached again
+            let previous = {
+                let mut key_cache = self.cached_keys.lock().unwrap();
+                let Some(Some(cached)) = key_cache.get_mut(index) else {
+                    continue;
+                };
+                let previous = fingerprints(cached);
+                // The replaced keys are wiped on drop
+                *cached = keys;
+                previous
+            };
+            self.record_key_sources(index, &identities);
+            self.store_details(index, name.clone(), rejected);
+
+            if previous != current {
+                info!(
+                    "Key of secret {} ('{}') rotated: {} replaced by {}",
+                    secret_id,
+                    name,
+                    previous.join(", "),
+                    current.join(", ")
+                );
+                rotated.push(secret_id);
+            }
+        }
+        rotated
+    }
+
+    /// Drop every cached key, wiping the memory that held it
+    pub fn clear_cache(&self) {
+        let mut cache = self.cached_keys.lock().unwrap();
+        for slot in cache.iter_mut() {
+            slot.take();
+        }
+        debug!("Key cache wiped");
+    }
+
+    /// Drop the cached keys of the secret at `index`, wiping the memory that held them
+    fn evict(&self, index: usize) {
+        let mut cache = self.cached_keys.lock().unwrap();
+        if let Some(slot) = cache.get_mut(index) {
+            slot.take();
+        }
+    }
+
+    /// Key strength and algorithm policy applied to the served keys
+    pub fn key_policy(&self) -> &KeyPolicy {
+        &self.key_policy
+    }
+
+    /// Whether `key` is stored in one of the configured secrets
+    pub async fn holds_key(&self, key: &KeyData) -> bool {
+        for index in self.exposed() {
+            if !self.is_exhausted(index) {
+                // Loading failures are reported when the key is used
+                let _ = self.get_identities(index).await;
+            }
+            if self.known_keys(index).contains(key) {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock_digest.lock().unwrap().is_some()
+    }
+
+    /// Whether the secret at `index` has used up its allowed signatures
+    fn is_exhausted(&self, index: usize) -> bool {
+        let options = self.key_options(index);
+        let Some(max_signatures) = options.max_signatures else {
+            return false;
+        };
+        let mut counters = self.signature_counters.lock().unwrap();
+        let Some(counter) = counters.get_mut(index) else {
+            return false;
+        };
+        counter.expire_window(options.max_signatures_window);
+        counter.count >= max_signatures
+    }
+
+    /// Take one signature from the allowance of the secret at `index`.
+    /// Returns how many signatures are left, if the secret is capped.
+    fn reserve_signature(&self, index: usize) -> Result<Option<u64>, AgentError> {
+        let options = self.key_options(index);
+        let Some(max_signatures) = options.max_signatures else {
+            return Ok(None);
+        };
+        let mut counters = self.signature_counters.lock().unwrap();
+        let counter = counters.get_mut(index).ok_or_else(|| {
+            AgentError::other(Box::new(std::io::Error::other("Invalid key index")))
+        })?;
+        counter.expire_window(options.max_signatures_window);
+        if counter.count >= max_signatures {
+            return Err(AgentError::other(Box::new(std::io::Error::other(
+                "Signature limit reached for this key",
+            ))));
+        }
+        counter.count += 1;
+        counter.window_start.get_or_insert_with(Instant::now);
+        Ok(Some(max_signatures - counter.count))
+    }
+
+    /// Take one signature from the allowance of every served secret holding `key`
+    /// and capped by `max_signatures`. Returns those secrets and how many
+    /// signatures each has left.
+    fn reserve_signatures(&self, key: &KeyData) -> Result<Vec<(usize, u64)>, AgentError> {
+        let mut reserved = Vec::new();
+        for index in self.exposed() {
+            if !self.known_keys(index).contains(key) {
+                continue;
+            }
+            match self.reserve_signature(index) {
+                Ok(Some(remaining)) => reserved.push((index, remaining)),
+                Ok(None) => {}
+                Err(e) => {
+                    for (capped, _) in &reserved {
+                        self.release_signature(*capped);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Hand back a signature reserved for the secret at `index` that was not produced
+    fn release_signature(&self, index: usize) {
+        let mut counters = self.signature_counters.lock().unwrap();
+        if let Some(counter) = counters.get_mut(index) {
+            counter.count = counter.count.saturating_sub(1);
+            if counter.count == 0 {
+                counter.window_start = None;
+            }
+        }
+    }
+
+    /// Start a new allowance for every capped key, on request of the operator
+    /// (`vault-conductor reset-counters`), never of a client
+    pub fn reset_signature_counters(&self) {
+        let mut counters = self.signature_counters.lock().unwrap();
+        for counter in counters.iter_mut() {
+            *counter = SignatureCounter::default();
+        }
+        info!("Signature counters reset");
     }
 }
 
+/// Sign `data` with `key`. RSA signatures are created here rather than through
+/// the `ssh-key` signer, which always hashes with SHA-512.
+fn create_signature(key: &PrivateKey, hash: Option<HashAlg>, data: &[u8]) -> Result<Signature> {
+    let Some(keypair) = key.key_data().rsa() else {
+        return Ok(key.try_sign(data)?);
+    };
+
+    let private_key = rsa::RsaPrivateKey::from_components(
+        to_biguint(&keypair.public.n)?,
+        to_biguint(&keypair.public.e)?,
+        to_biguint(&keypair.private.d)?,
+        vec![
+            to_biguint(&keypair.private.p)?,
+            to_biguint(&keypair.private.q)?,
+        ],
+    )?;
+    let signature = match hash {
+        Some(HashAlg::Sha256) => rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(private_key)
+            .try_sign(data)?
+            .to_vec(),
+        Some(HashAlg::Sha512) => rsa::pkcs1v15::SigningKey::<sha2::Sha512>::new(private_key)
+            .try_sign(data)?
+            .to_vec(),
+        None => rsa::pkcs1v15::SigningKey::<sha1::Sha1>::new(private_key)
+            .try_sign(data)?
+            .to_vec(),
+        Some(other) => return Err(anyhow!("Unsupported RSA signature hash: {}", other)),
+    };
+    Ok(Signature::new(Algorithm::Rsa { hash }, signature)?)
+}
+
+fn to_biguint(value: &Mpint) -> Result<rsa::BigUint> {
+    value
+        .as_positive_bytes()
+        .map(rsa::BigUint::from_bytes_be)
+        .ok_or_else(|| anyhow!("Invalid RSA key component"))
+}
+
+/// Namespace of an `ssh-keygen -Y sign` blob, `None` for any other data.
+/// The blob starts with the `SSHSIG` magic followed by the namespace as an SSH string.
+fn sshsig_namespace(data: &[u8]) -> Option<String> {
+    let rest = data.strip_prefix(b"SSHSIG")?;
+    let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let namespace = rest.get(4..4 + len)?;
+    String::from_utf8(namespace.to_vec()).ok()
+}
+
+fn passphrase_digest(passphrase: &str) -> Zeroizing<[u8; 64]> {
+    Zeroizing::new(Sha512::digest(passphrase.as_bytes()).into())
+}
+
 #[async_trait]
 impl<F: SecretFetcher + Clone + 'static> Session for BitwardenAgent<F> {
     async fn request_identities(&mut self) -> Result<Vec<Identity>, AgentError> {
-        debug!("Request identities called");
+        debug!("[{}] Request identities called", self.connection);
 
         let mut identities = Vec::new();
+        // Keys stored in several secrets are advertised once
+        let mut advertised = HashSet::new();
 
-        for index in 0..self.secret_ids.len() {
-            match self.get_private_key(index).await {
-                Ok(key) => {
-                    let pubkey = key.public_key();
-
-                    // Log the public key details for debugging
-                    let key_data = pubkey.key_data();
-                    debug!(
-                        "Returning identity {} - algorithm: {:?}, fingerprint: {}",
-                        index,
-                        pubkey.algorithm(),
-                        pubkey.fingerprint(ssh_key::HashAlg::Sha256)
-                    );
+        // A locked agent advertises no keys
+        if self.is_locked() {
+            debug!(
+                "[{}] Agent is locked, returning no identities",
+                self.connection
+            );
+            return Ok(identities);
+        }
 
-                    // Also log the key in authorized_keys format for comparison
-                    let auth_key_format =
-                        pubkey.to_openssh().unwrap_or_else(|_| "error".to_string());
-                    debug!("Public key {} (OpenSSH format): {}", index, auth_key_format);
+        let mut loaded = Vec::new();
+        for index in self.exposed() {
+            // Keys that used up their signatures stay hidden until the counter resets
+            if self.is_exhausted(index) {
+                debug!(
+                    "[{}] Key {} reached its signature limit, not advertising it",
+                    self.connection, index
+                );
+                // Copies stored in other secrets must not bypass the limit
+                advertised.extend(self.known_keys(index));
+                continue;
+            }
 
-                    identities.push(Identity {
-                        credential: PublicCredential::Key(key_data.clone()),
-                        comment: self.get_cached_key_name(index),
-                    });
-                }
+            match self.get_identities(index).await {
+                Ok(keys) => loaded.push((index, keys)),
                 Err(e) => {
                     // Log warning but continue with other keys
                     let secret_id = self
                         .secret_ids
+                        .lock()
+                        .unwrap()
                         .get(index)
                         .map(|id| id.to_string())
                         .unwrap_or_else(|| "unknown".to_string());
                     warn!(
-                        "Failed to load key at position {} (secret ID: {}): {}. Skipping this key.",
-                        index, secret_id, e
+                        "[{}] Failed to load key at position {} (secret ID: {}): {}. Skipping this key.",
+                        self.connection, index, secret_id, e
+                    );
+                }
+            }
+        }
+
+        // A key hidden in one of the secrets holding it is not advertised for the others
+        let hidden: HashSet<KeyData> = loaded
+            .iter()
+            .flat_map(|(_, keys)| keys)
+            .filter(|key| key.hidden)
+            .map(|key| key.public_key.key_data().clone())
+            .collect();
+
+        for (index, keys) in loaded {
+            for key in keys {
+                let pubkey = &key.public_key;
+                if hidden.contains(pubkey.key_data()) {
+                    debug!(
+                        "[{}] Key {} ({}) is hidden, not advertising it",
+                        self.connection,
+                        index,
+                        pubkey.fingerprint(HashAlg::Sha256)
+                    );
+                    continue;
+                }
+                if !advertised.insert(pubkey.key_data().clone()) {
+                    debug!(
+                        "[{}] Key {} of secret {} already advertised, skipping duplicate",
+                        self.connection,
+                        pubkey.fingerprint(HashAlg::Sha256),
+                        index
                     );
+                    continue;
+                }
+
+                // Log the public key details for debugging
+                debug!(
+                    "[{}] Returning identity {} - algorithm: {:?}, fingerprint: {}",
+                    self.connection,
+                    index,
+                    pubkey.algorithm(),
+                    pubkey.fingerprint(ssh_key::HashAlg::Sha256)
+                );
+
+                // Also log the key in authorized_keys format for comparison
+                let auth_key_format = pubkey.to_openssh().unwrap_or_else(|_| "error".to_string());
+                debug!(
+                    "[{}] Public key {} (OpenSSH format): {}",
+                    self.connection, index, auth_key_format
+                );
+
+                let comment = key.comment;
+                if let Some(certificate) = key.certificate {
+                    identities.push(Identity {
+                        credential: PublicCredential::Cert(Box::new(certificate)),
+                        comment: comment.clone(),
+                    });
                 }
+                identities.push(Identity {
+                    credential: PublicCredential::Key(pubkey.key_data().clone()),
+                    comment,
+                });
             }
         }
 
@@ -141,71 +1086,233 @@ impl<F: SecretFetcher + Clone + 'static> Session for BitwardenAgent<F> {
 
     async fn sign(&mut self, request: SignRequest) -> Result<Signature, AgentError> {
         debug!(
-            "Sign request - flags: 0x{:x}, data length: {} bytes",
+            "[{}] Sign request - flags: 0x{:x}, data length: {} bytes",
+            self.connection,
             request.flags,
             request.data.len()
         );
         debug!(
-            "Data (first 100 bytes): {:?}",
+            "[{}] Data (first 100 bytes): {:?}",
+            self.connection,
             &request.data[..request.data.len().min(100)]
         );
 
-        // Find which key matches the requested public key
-        for index in 0..self.secret_ids.len() {
-            match self.get_private_key(index).await {
-                Ok(key) => {
-                    let pubkey = key.public_key();
-
-                    // Compare the public keys
-                    if let PublicCredential::Key(request_key_data) = &request.credential {
-                        // Skip if pubkeys do not match and continue with other configured keys
-                        if pubkey.key_data() != request_key_data {
-                            continue;
-                        }
-
-                        // For SSH agent protocol, we need to create a RAW signature (not OpenSSH format)
-                        // using the underlying keypair's try_sign method
-                        let signature_bytes = key.try_sign(&request.data).map_err(|e| {
-                            AgentError::other(Box::new(std::io::Error::other(format!(
-                                "Signing failed: {}",
-                                e
-                            ))))
-                        })?;
-
-                        debug!(
-                            "Signature created successfully with key {}, {} bytes",
-                            index,
-                            signature_bytes.as_bytes().len()
-                        );
-
-                        // Return the signature in SSH agent format
-                        return Ok(signature_bytes);
+        if self.is_locked() {
+            return Err(AgentError::other(Box::new(std::io::Error::other(
+                "Agent is locked",
+            ))));
+        }
+
+        // Certificates are signed with the key they certify
+        let request_key = match &request.credential {
+            PublicCredential::Key(key_data) => key_data.clone(),
+            PublicCredential::Cert(certificate) => certificate.public_key().clone(),
+        };
+        let hash = self
+            .key_policy
+            .signature_hash(request_key.algorithm(), request.flags)
+            .map_err(|e| {
+                warn!(
+                    "[{}] Sign request refused by key policy: {}",
+                    self.connection, e
+                );
+                AgentError::other(Box::new(std::io::Error::other(e.to_string())))
+            })?;
+
+        // Secrets that could not be loaded, duplicates in other secrets act as fallback
+        let mut unavailable = Vec::new();
+        // Secrets holding the requested key, with its position and identity in each
+        let mut copies = Vec::new();
+
+        // Find which keys match the requested public key
+        for index in self.exposed() {
+            if self.is_exhausted(index) {
+                // Copies stored in other secrets must not bypass the limit
+                if self.known_keys(index).contains(&request_key) {
+                    return Err(AgentError::other(Box::new(std::io::Error::other(
+                        "Signature limit reached for this key",
+                    ))));
+                }
+                continue;
+            }
+
+            match self.get_identities(index).await {
+                Ok(keys) => {
+                    if let Some(position) = keys
+                        .iter()
+                        .position(|key| key.public_key.key_data() == &request_key)
+                    {
+                        copies.push((index, position, keys[position].clone()));
                     }
                 }
                 Err(e) => {
                     // Log warning and continue trying other keys
                     let secret_id = self
                         .secret_ids
+                        .lock()
+                        .unwrap()
                         .get(index)
                         .map(|id| id.to_string())
                         .unwrap_or_else(|| "unknown".to_string());
                     warn!(
-                        "Failed to load key {} (secret ID: {}) while signing: {}. Trying next key.",
-                        index, secret_id, e
+                        "[{}] Failed to load key {} (secret ID: {}) while signing: {}. Trying next key.",
+                        self.connection, index, secret_id, e
                     );
-                    continue;
+                    unavailable.push(index);
                 }
             }
         }
 
-        // No matching key found
-        Err(AgentError::other(Box::new(std::io::Error::other(
-            "Key not found",
-        ))))
+        // Sign with the first copy, under the most restrictive constraints of all of them
+        let Some((index, position, identity)) = copies.first().cloned() else {
+            return Err(AgentError::other(Box::new(std::io::Error::other(
+                "Key not found",
+            ))));
+        };
+        let constraints =
+            merge_constraints(copies.iter().map(|(_, _, identity)| &identity.constraints));
+        let comment = &identity.comment;
+
+        let fallback_from = self.unavailable_sources(&request_key, &unavailable);
+        if !fallback_from.is_empty() {
+            warn!(
+                "[{}] Secret(s) {} holding key '{}' could not be loaded, \
+                using the copy stored in secret {}",
+                self.connection,
+                fallback_from
+                    .iter()
+                    .map(SecretId::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                comment,
+                self.secret_id(index)
+            );
+        }
+
+        if let Some(namespaces) = &constraints.namespaces {
+            let namespace = sshsig_namespace(&request.data);
+            if !namespace
+                .as_ref()
+                .is_some_and(|namespace| namespaces.contains(namespace))
+            {
+                warn!(
+                    "[{}] Key '{}' may only sign for namespaces [{}], refusing to sign {}",
+                    self.connection,
+                    comment,
+                    namespaces.join(", "),
+                    namespace
+                        .map(|namespace| format!("for namespace '{}'", namespace))
+                        .unwrap_or_else(|| "data outside of ssh-keygen -Y sign".to_string())
+                );
+                return Err(AgentError::other(Box::new(std::io::Error::other(
+                    "Signature namespace not allowed for this key",
+                ))));
+            }
+        }
+
+        if constraints.confirm {
+            let fingerprint = identity.public_key.fingerprint(HashAlg::Sha256);
+            if !confirm_key_use(comment, &fingerprint, &self.connection).await {
+                warn!(
+                    "[{}] Use of key '{}' was not confirmed, refusing to sign",
+                    self.connection, comment
+                );
+                return Err(AgentError::other(Box::new(std::io::Error::other(
+                    "Key use not confirmed",
+                ))));
+            }
+        }
+
+        // Every secret holding the key counts the signature, so that the smallest
+        // limit applies. Reserved up front so that concurrent requests cannot exceed
+        // it, and handed back if no signature is produced.
+        let reserved = self.reserve_signatures(&request_key)?;
+        let signature_bytes = match self
+            .sign_with(index, position, &request_key, hash, &request.data)
+            .await
+        {
+            Ok(signature) => signature,
+            Err(e) => {
+                for (capped, _) in &reserved {
+                    self.release_signature(*capped);
+                }
+                return Err(e);
+            }
+        };
+
+        debug!(
+            "[{}] Signature created successfully with key {}, {} bytes",
+            self.connection,
+            index,
+            signature_bytes.as_bytes().len()
+        );
+
+        for (capped, remaining) in reserved {
+            if remaining == 0 {
+                warn!(
+                    "[{}] Key {} (secret ID: {}) reached its signature limit, \
+                    evicting it until the counter is reset",
+                    self.connection,
+                    capped,
+                    self.secret_id(capped)
+                );
+                self.evict(capped);
+            }
+        }
+
+        // Return the signature in SSH agent format
+        Ok(signature_bytes)
+    }
+
+    async fn lock(&mut self, key: String) -> Result<(), AgentError> {
+        let key = Zeroizing::new(key);
+        {
+            let mut lock_digest = self.lock_digest.lock().unwrap();
+            if lock_digest.is_some() {
+                return Err(AgentError::other(Box::new(std::io::Error::other(
+                    "Agent is already locked",
+                ))));
+            }
+            *lock_digest = Some(passphrase_digest(&key));
+        }
+
+        // Nothing stays cached while locked, other views fetch the keys again if needed
+        for index in self.exposed() {
+            self.evict(index);
+        }
+        info!("[{}] Agent locked", self.connection);
+        Ok(())
+    }
+
+    async fn unlock(&mut self, key: String) -> Result<(), AgentError> {
+        let key = Zeroizing::new(key);
+        {
+            let mut lock_digest = self.lock_digest.lock().unwrap();
+            match lock_digest.as_ref() {
+                Some(digest) if **digest == *passphrase_digest(&key) => *lock_digest = None,
+                Some(_) => {
+                    warn!("[{}] Failed attempt to unlock the agent", self.connection);
+                    return Err(AgentError::other(Box::new(std::io::Error::other(
+                        "Incorrect passphrase",
+                    ))));
+                }
+                None => {
+                    return Err(AgentError::other(Box::new(std::io::Error::other(
+                        "Agent is not locked",
+                    ))));
+                }
+            }
+        }
+
+        info!("[{}] Agent unlocked", self.connection);
+        Ok(())
     }
 
     async fn extension(&mut self, extension: Extension) -> Result<Option<Extension>, AgentError> {
-        debug!("Extension request: {}", extension.name);
+        debug!(
+            "[{}] Extension request: {}",
+            self.connection, extension.name
+        );
 
         // Return None to indicate the extension is not supported but don't error
         // This allows clients to gracefully handle unsupported extensions
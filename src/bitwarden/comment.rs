@@ -0,0 +1,97 @@
+use crate::bitwarden::agent::SecretId;
+use anyhow::{bail, Result};
+use ssh_key::{HashAlg, PublicKey};
+
+/// Placeholders accepted in `comment_template`
+const PLACEHOLDERS: &[&str] = &[
+    "name",
+    "project",
+    "uuid",
+    "fingerprint",
+    "fingerprint_short",
+    "algorithm",
+];
+
+/// Length of `{fingerprint_short}`, in base64 characters of the SHA256 fingerprint
+const FINGERPRINT_SHORT_LEN: usize = 8;
+
+/// Values a comment template is rendered with
+pub struct CommentFields<'a> {
+    pub name: &'a str,
+    pub project: Option<&'a str>,
+    pub secret_id: &'a SecretId,
+    pub public_key: &'a PublicKey,
+}
+
+/// Comment used when nothing better is known about a secret
+pub fn fallback_comment(secret_id: &SecretId) -> String {
+    format!("vc:{}", secret_id)
+}
+
+/// Check that `template` only uses known placeholders and has balanced braces
+pub fn validate_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            bail!("Unclosed '{{' in comment template '{}'", template);
+        };
+        let placeholder = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            bail!(
+                "Unknown placeholder '{{{}}}' in comment template '{}', expected one of: {}",
+                placeholder,
+                template,
+                PLACEHOLDERS
+                    .iter()
+                    .map(|placeholder| format!("{{{}}}", placeholder))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        bail!("Unmatched '}}' in comment template '{}'", template);
+    }
+    Ok(())
+}
+
+/// Render a template validated with [`validate_template`]
+pub fn render_comment(template: &str, fields: &CommentFields) -> String {
+    let mut comment = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        comment.push_str(&rest[..start]);
+        match placeholder_value(&rest[start + 1..end], fields) {
+            Some(value) => comment.push_str(&value),
+            None => comment.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    comment.push_str(rest);
+    comment
+}
+
+fn placeholder_value(placeholder: &str, fields: &CommentFields) -> Option<String> {
+    let value = match placeholder {
+        "name" if fields.name.is_empty() => fallback_comment(fields.secret_id),
+        "name" => fields.name.to_string(),
+        "project" => fields.project.unwrap_or_default().to_string(),
+        "uuid" => fields.secret_id.to_string(),
+        "fingerprint" => fields.public_key.fingerprint(HashAlg::Sha256).to_string(),
+        "fingerprint_short" => fields
+            .public_key
+            .fingerprint(HashAlg::Sha256)
+            .to_string()
+            .trim_start_matches("SHA256:")
+            .chars()
+            .take(FINGERPRINT_SHORT_LEN)
+            .collect(),
+        "algorithm" => fields.public_key.algorithm().to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
@@ -0,0 +1,95 @@
+use crate::bitwarden::connection::ConnectionContext;
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use ssh_key::Fingerprint;
+use std::io::IsTerminal;
+use std::process::Stdio;
+use tokio::process::Command;
+use zeroize::Zeroizing;
+
+/// Ask the user to approve a signature, as `ssh-agent` does for keys added with `ssh-add -c`.
+///
+/// The program in `SSH_ASKPASS` is run with `SSH_ASKPASS_PROMPT=confirm`, the signature
+/// is allowed only if it exits successfully. Without `SSH_ASKPASS` nothing can be asked,
+/// so the signature is refused. The prompt names the process asking for the signature
+/// when `connection` knows it.
+pub async fn confirm_key_use(
+    comment: &str,
+    fingerprint: &Fingerprint,
+    connection: &ConnectionContext,
+) -> bool {
+    let Some(askpass) = std::env::var_os("SSH_ASKPASS") else {
+        warn!(
+            "[{}] Key '{}' requires confirmation but SSH_ASKPASS is not set, refusing to sign",
+            connection, comment
+        );
+        return false;
+    };
+
+    let prompt = match connection.requester() {
+        Some(requester) => format!(
+            "Allow use of key {} by {}?\nKey fingerprint {}.",
+            comment, requester, fingerprint
+        ),
+        None => format!(
+            "Allow use of key {}?\nKey fingerprint {}.",
+            comment, fingerprint
+        ),
+    };
+    // Under `serve-stdio` the agent protocol runs over stdin and stdout,
+    // the program must not read from or write to them
+    match Command::new(&askpass)
+        .arg(prompt)
+        .env("SSH_ASKPASS_PROMPT", "confirm")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+    {
+        Ok(status) => {
+            debug!(
+                "[{}] Confirmation for key '{}' exited with {}",
+                connection, comment, status
+            );
+            status.success()
+        }
+        Err(e) => {
+            warn!("Failed to run SSH_ASKPASS program {:?}: {}", askpass, e);
+            false
+        }
+    }
+}
+
+/// Ask the user for a password: on the terminal when there is one, through the program
+/// in `SSH_ASKPASS` otherwise (e.g. when the agent was started in the background).
+pub async fn ask_password(prompt: &str) -> Result<Zeroizing<String>> {
+    if std::io::stdin().is_terminal() {
+        let prompt = prompt.to_string();
+        let password = tokio::task::spawn_blocking(move || rpassword::prompt_password(prompt))
+            .await
+            .context("Password prompt failed")?
+            .context("Failed to read the password from the terminal")?;
+        return Ok(Zeroizing::new(password));
+    }
+
+    let Some(askpass) = std::env::var_os("SSH_ASKPASS") else {
+        bail!("Cannot ask for the password: not on a terminal and SSH_ASKPASS is not set");
+    };
+    // The password is read from a pipe, never from the stdout of the agent,
+    // which carries the agent protocol under `serve-stdio`
+    let output = Command::new(&askpass)
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .output()
+        .await
+        .with_context(|| format!("Failed to run SSH_ASKPASS program {:?}", askpass))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        bail!("Password prompt was cancelled");
+    }
+    let password = std::str::from_utf8(&stdout).context("Password is not valid UTF-8")?;
+    Ok(Zeroizing::new(
+        password.trim_end_matches(['\r', '\n']).to_string(),
+    ))
+}
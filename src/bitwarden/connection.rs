@@ -0,0 +1,172 @@
+use crate::bitwarden::agent::{BitwardenAgent, SecretFetcher};
+use crate::bitwarden::shutdown::{RequestTracker, TrackedSession};
+use crate::bitwarden::upstream::{ProxyAgent, UpstreamConnector};
+use log::{debug, info};
+use ssh_agent_lib::agent::{Agent, Session};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::{UnixListener, UnixStream};
+
+/// Process on the other end of a connection
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub pid: Option<i32>,
+    pub uid: u32,
+    /// Command line of the process, if it can be read
+    pub command: Option<String>,
+}
+
+impl PeerInfo {
+    /// Name of the program, e.g. `ssh` for `/usr/bin/ssh git@github.com`
+    pub fn program(&self) -> Option<&str> {
+        let command = self.command.as_deref()?;
+        let program = command.split(' ').next()?;
+        program.rsplit('/').next()
+    }
+}
+
+/// Client of an agent session, attached to log lines and confirmation prompts
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionContext {
+    /// Sequence number of the connection, unique within the agent process
+    pub id: u64,
+    pub peer: Option<PeerInfo>,
+}
+
+impl ConnectionContext {
+    /// Context of a connection accepted on an agent socket
+    pub fn from_stream(id: u64, stream: &UnixStream) -> Self {
+        let peer = match stream.peer_cred() {
+            Ok(credentials) => Some(PeerInfo {
+                pid: credentials.pid(),
+                uid: credentials.uid(),
+                command: credentials.pid().and_then(read_command_line),
+            }),
+            Err(e) => {
+                debug!(
+                    "Failed to read peer credentials of connection {}: {}",
+                    id, e
+                );
+                None
+            }
+        };
+        Self { id, peer }
+    }
+
+    /// Context of the session served on stdin/stdout, the client is the parent process
+    pub fn stdio() -> Self {
+        let pid = std::os::unix::process::parent_id() as i32;
+        Self {
+            id: 0,
+            peer: Some(PeerInfo {
+                pid: Some(pid),
+                // SAFETY: getuid has no preconditions and cannot fail
+                uid: unsafe { libc::getuid() },
+                command: read_command_line(pid),
+            }),
+        }
+    }
+
+    /// Describe the requesting process for a confirmation prompt, e.g. `ssh (pid 1234)`
+    pub fn requester(&self) -> Option<String> {
+        let peer = self.peer.as_ref()?;
+        let program = peer.program().unwrap_or("unknown process");
+        Some(match peer.pid {
+            Some(pid) => format!("{} (pid {})", program, pid),
+            None => program.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "conn {}", self.id)?;
+        if let Some(peer) = &self.peer {
+            if let Some(pid) = peer.pid {
+                write!(f, " pid {}", pid)?;
+            }
+            write!(f, " uid {}", peer.uid)?;
+            if let Some(program) = peer.program() {
+                write!(f, " {}", program)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Command line of process `pid`, arguments separated by spaces
+#[cfg(target_os = "linux")]
+fn read_command_line(pid: i32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let command = raw
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!command.is_empty()).then_some(command)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_command_line(_pid: i32) -> Option<String> {
+    None
+}
+
+/// Session that can be told which connection it serves
+pub trait ConnectionSession: Session + Clone {
+    fn with_connection(self, connection: ConnectionContext) -> Self;
+}
+
+impl<F: SecretFetcher + Clone + 'static> ConnectionSession for BitwardenAgent<F> {
+    fn with_connection(self, connection: ConnectionContext) -> Self {
+        BitwardenAgent::with_connection(self, connection)
+    }
+}
+
+impl<F: SecretFetcher + Clone + 'static, U: UpstreamConnector> ConnectionSession
+    for ProxyAgent<F, U>
+{
+    fn with_connection(self, connection: ConnectionContext) -> Self {
+        ProxyAgent::with_connection(self, connection)
+    }
+}
+
+/// Opens a session per accepted connection, tagged with a [`ConnectionContext`]
+/// and with its requests tracked for shutdown
+pub struct ConnectionAcceptor<A: ConnectionSession> {
+    agent: A,
+    next_id: Arc<AtomicU64>,
+    requests: Arc<RequestTracker>,
+}
+
+impl<A: ConnectionSession> ConnectionAcceptor<A> {
+    /// Accept connections for `agent`, numbering them from `next_id`.
+    /// Both `next_id` and `requests` are shared by the sockets of the process.
+    pub fn new(agent: A, next_id: Arc<AtomicU64>, requests: Arc<RequestTracker>) -> Self {
+        Self {
+            agent,
+            next_id,
+            requests,
+        }
+    }
+}
+
+impl<A: ConnectionSession> Agent<UnixListener> for ConnectionAcceptor<A> {
+    fn new_session(&mut self, socket: &UnixStream) -> impl Session {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let connection = ConnectionContext::from_stream(id, socket);
+        match connection.peer.as_ref() {
+            Some(peer) => info!(
+                "[{}] Connection accepted from: {}",
+                connection,
+                peer.command.as_deref().unwrap_or("unknown command")
+            ),
+            None => info!("[{}] Connection accepted", connection),
+        }
+        TrackedSession::new(
+            self.agent.clone().with_connection(connection),
+            self.requests.clone(),
+        )
+    }
+}
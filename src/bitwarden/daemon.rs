@@ -0,0 +1,297 @@
+use crate::file_manager::{
+    cleanup_files, get_socket_file_path, remove_profile_socket_files, remove_socket,
+    write_profile_sockets,
+};
+use crate::secure_memory::disable_core_dumps;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use ssh_agent_lib::agent::listen;
+use ssh_agent_lib::error::AgentError;
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+#[cfg(not(windows))]
+use tokio::net::UnixListener as Listener;
+use tokio::task::JoinSet;
+
+// Import from our lib
+use crate::backends;
+use crate::bitwarden::agent::{BitwardenAgent, SecretFetcher, SecretId};
+use crate::bitwarden::connection::ConnectionAcceptor;
+use crate::bitwarden::refresh::{self, OperatorSignals, Refresher};
+use crate::bitwarden::shutdown::RequestTracker;
+use crate::bitwarden::upstream::{ProxyAgent, UnixSocketUpstream};
+use crate::config::{Config, KeyOptions};
+
+/// Secret IDs listed in the configuration, in the format of the backend
+fn secret_ids(ids: &[String]) -> Vec<SecretId> {
+    ids.iter().map(SecretId::new).collect()
+}
+
+/// Names of the configured profiles, sorted for a stable serving order
+fn profile_names(config: &Config) -> Vec<&String> {
+    let mut names: Vec<&String> = config.profiles.keys().collect();
+    names.sort();
+    names
+}
+
+/// Secrets served to clients: the ones of `profile`, or `bw_secret_ids` without one
+pub fn served_secret_ids(config: &Config, profile: Option<&str>) -> Result<Vec<SecretId>> {
+    match profile {
+        Some(name) => {
+            let profile = config
+                .profiles
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown profile '{}'", name))?;
+            Ok(secret_ids(&profile.secret_ids))
+        }
+        None => Ok(secret_ids(&config.bw_secret_ids)),
+    }
+}
+
+/// Add the secrets `fetcher` discovers in the configured projects to `bw_secret_ids`.
+/// The configured secrets are still served when discovery fails.
+pub async fn add_discovered_secrets<F: SecretFetcher>(config: &mut Config, fetcher: &F) {
+    if config.bw_project_ids.is_empty() {
+        return;
+    }
+    match fetcher.discover_secrets().await {
+        Ok(ids) => {
+            for id in ids {
+                let id = id.to_string();
+                if !config.bw_secret_ids.contains(&id) {
+                    config.bw_secret_ids.push(id);
+                }
+            }
+        }
+        Err(e) => warn!(
+            "Failed to discover secrets in the configured projects: {:#}",
+            e
+        ),
+    }
+}
+
+/// Create the agent for the configured keys, profiles included
+/// (will fetch secrets lazily on first use)
+pub fn build_agent<F: SecretFetcher + Clone>(
+    config: &Config,
+    fetcher: Arc<F>,
+) -> BitwardenAgent<F> {
+    let mut ids = secret_ids(&config.bw_secret_ids);
+    for name in profile_names(config) {
+        for id in secret_ids(&config.profiles[name].secret_ids) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    let key_options: HashMap<SecretId, KeyOptions> = config
+        .key_options
+        .iter()
+        .map(|(id, options)| (SecretId::new(id), options.clone()))
+        .collect();
+
+    BitwardenAgent::new(fetcher, ids)
+        .with_key_options(&key_options)
+        .with_key_policy(config.key_policy.clone())
+        .with_comment_template(config.comment_template.clone())
+}
+
+/// Bind an agent socket readable and writable by the owner only
+fn bind_socket(socket_path: &PathBuf) -> Result<Listener> {
+    // Remove a stale socket, anything else at the path is left untouched
+    remove_socket(socket_path)?;
+    let listener = Listener::bind(socket_path)
+        .with_context(|| format!("Failed to bind socket at {}", socket_path.display()))?;
+    // Set socket permissions to 0600 (read/write for owner only)
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))
+        .context("Failed to set socket permissions")?;
+    Ok(listener)
+}
+
+/// Serve the backend keys alone, or merged with the ones of the upstream agent
+async fn serve<F: SecretFetcher + Clone + 'static>(
+    listener: Listener,
+    agent: BitwardenAgent<F>,
+    upstream_socket: Option<PathBuf>,
+    next_id: Arc<AtomicU64>,
+    requests: Arc<RequestTracker>,
+) -> Result<(), AgentError> {
+    match upstream_socket {
+        Some(path) => {
+            let agent = ProxyAgent::new(agent, UnixSocketUpstream::new(path));
+            listen(listener, ConnectionAcceptor::new(agent, next_id, requests)).await
+        }
+        None => listen(listener, ConnectionAcceptor::new(agent, next_id, requests)).await,
+    }
+}
+
+/// Wipe the cached keys, then remove the PID file and every socket
+fn shutdown<F: SecretFetcher + Clone>(
+    cache: &BitwardenAgent<F>,
+    profile_sockets: &[PathBuf],
+) -> Result<()> {
+    cache.clear_cache();
+    cleanup_files()?;
+    remove_profile_socket_files(profile_sockets)
+}
+
+pub async fn start_agent_foreground(config_file: Option<String>) -> Result<()> {
+    // Keep key material out of core dumps before any secret is loaded
+    disable_core_dumps();
+    // Handled as soon as the agent runs, connecting to the backend may take a while
+    let operator_signals = OperatorSignals::install()?;
+
+    let socket_path = get_socket_file_path(None);
+    // Load configuration
+    let mut config = Config::load(&config_file).context("Failed to load configuration")?;
+
+    // Socket and secrets of each profile, served next to the default socket
+    let mut profile_sockets: Vec<PathBuf> = Vec::new();
+    for name in profile_names(&config) {
+        let profile = &config.profiles[name];
+        let path = profile.socket_path(name);
+        if path == socket_path || profile_sockets.contains(&path) {
+            return Err(anyhow!(
+                "Socket of profile '{}' ({}) is already used by another socket",
+                name,
+                path.display()
+            ));
+        }
+        profile_sockets.push(path);
+    }
+
+    let upstream_socket = config.upstream_agent.as_ref().map(PathBuf::from);
+    if let Some(upstream) = &upstream_socket {
+        if *upstream == socket_path || profile_sockets.contains(upstream) {
+            return Err(anyhow!(
+                "upstream_agent points to a vault-conductor socket itself ({})",
+                upstream.display()
+            ));
+        }
+        info!(
+            "Merging identities from upstream agent at {}",
+            upstream.display()
+        );
+    }
+
+    // Authenticate to the configured backend, resolving the secret names
+    let fetcher = Arc::new(backends::connect(&mut config).await?);
+    let configured_ids = served_secret_ids(&config, None)?;
+    add_discovered_secrets(&mut config, fetcher.as_ref()).await;
+
+    // Create the agent instance (will fetch secrets lazily on first use),
+    // every socket serves a view of it sharing the client and the key cache
+    let agent = build_agent(&config, fetcher.clone());
+    let cache = agent.clone();
+
+    // Connections are numbered across all sockets, to tell them apart in the logs
+    let next_id = Arc::new(AtomicU64::new(1));
+    // Requests in flight on any socket, waited for on shutdown
+    let requests = Arc::new(RequestTracker::default());
+    let mut sockets = JoinSet::new();
+    // Recorded before binding them, `stop` removes them even if the agent is killed
+    write_profile_sockets(&profile_sockets)?;
+    let default_ids = served_secret_ids(&config, None)?;
+    let default_view = agent.view(&default_ids)?;
+    sockets.spawn(serve(
+        bind_socket(&socket_path)?,
+        default_view.clone(),
+        upstream_socket,
+        next_id.clone(),
+        requests.clone(),
+    ));
+    for (name, path) in profile_names(&config).into_iter().zip(&profile_sockets) {
+        let secret_ids = served_secret_ids(&config, Some(name))?;
+        info!(
+            "Serving {} secret(s) of profile '{}' on {}",
+            secret_ids.len(),
+            name,
+            path.display()
+        );
+        // Upstream keys stay on the default socket, profiles serve their secrets only
+        sockets.spawn(serve(
+            bind_socket(path)?,
+            agent.view(&secret_ids)?,
+            None,
+            next_id.clone(),
+            requests.clone(),
+        ));
+    }
+
+    // Pick up rotated keys and changes to the watched projects, on SIGHUP and periodically
+    let refresher = Refresher::new(fetcher, agent.clone(), default_view, configured_ids)
+        .with_watched_projects(!config.bw_project_ids.is_empty());
+    if let Some(interval) = config.get_refresh_interval() {
+        info!("Refreshing keys every {}s", interval.as_secs());
+    }
+    let refresh_task = tokio::spawn(refresh::run(
+        refresher,
+        config.get_refresh_interval(),
+        operator_signals,
+    ));
+
+    // Setup signal handlers for graceful shutdown
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
+
+    // Listen and process connections with signal handling
+    tokio::select! {
+        result = sockets.join_next() => {
+            // A socket stopped serving (unlikely in normal operation)
+            match result {
+                Some(Ok(Err(e))) => {
+                    shutdown(&cache, &profile_sockets)?;
+                    return Err(e.into());
+                }
+                Some(Err(e)) => {
+                    shutdown(&cache, &profile_sockets)?;
+                    return Err(e).context("Agent socket task failed");
+                }
+                Some(Ok(Ok(()))) | None => {}
+            }
+        }
+        _ = sigterm.recv() => {
+            info!("Received SIGTERM, gracefully shutting down...");
+        }
+        _ = sigint.recv() => {
+            info!("Received SIGINT (Ctrl+C), gracefully shutting down...");
+        }
+    }
+
+    // Stop accepting connections, then give the requests in flight time to finish
+    refresh_task.abort();
+    sockets.abort_all();
+    let timeout = config.get_shutdown_timeout();
+    if requests.in_flight() > 0 {
+        info!(
+            "Waiting up to {}s for {} request(s) in flight",
+            timeout.as_secs(),
+            requests.in_flight()
+        );
+    }
+    tokio::select! {
+        drained = requests.drain(timeout) => {
+            if !drained {
+                warn!(
+                    "{} request(s) still in flight after {}s, aborting them",
+                    requests.in_flight(),
+                    timeout.as_secs()
+                );
+            }
+        }
+        _ = sigterm.recv() => {
+            warn!("Received SIGTERM again, aborting the requests in flight");
+        }
+        _ = sigint.recv() => {
+            warn!("Received SIGINT again, aborting the requests in flight");
+        }
+    }
+    shutdown(&cache, &profile_sockets)?;
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use ssh_key::{Certificate, PrivateKey};
+use zeroize::Zeroizing;
+
+/// Usage constraints of a key, the agent-side equivalent of `ssh-add -t` and `ssh-add -c`
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyConstraints {
+    /// Seconds the key stays in memory before it is wiped and fetched again
+    #[serde(default)]
+    pub lifetime: Option<u64>,
+    /// Ask for confirmation through `SSH_ASKPASS` before every signature
+    #[serde(default)]
+    pub confirm: bool,
+    /// `ssh-keygen -Y sign` namespaces the key may sign for, anything else is refused
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+}
+
+/// Header of the option block in a secret note
+const NOTE_OPTIONS_HEADER: &str = "[vault-conductor]";
+
+/// Options read from the note of a secret, local `key_options` take precedence
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoteOptions {
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub confirm: Option<bool>,
+    #[serde(default)]
+    pub lifetime: Option<u64>,
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+    #[serde(default)]
+    pub hidden: Option<bool>,
+}
+
+/// Key parsed from a secret value, ready to be cached
+pub struct KeyEntry {
+    pub private_key: PrivateKey,
+    pub certificate: Option<Certificate>,
+    pub comment: Option<String>,
+    pub constraints: KeyConstraints,
+}
+
+/// Structured secret value holding several keys
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyBundle {
+    keys: Vec<BundledKey>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BundledKey {
+    /// OpenSSH private key, possibly encrypted
+    key: Zeroizing<String>,
+    /// OpenSSH certificate for the key (`ssh-...-cert-v01@openssh.com AAAA...`)
+    #[serde(default)]
+    certificate: Option<String>,
+    #[serde(default)]
+    comment: Option<String>,
+    #[serde(default)]
+    passphrase: Option<Zeroizing<String>>,
+    #[serde(default)]
+    constraints: KeyConstraints,
+}
+
+/// Parse a secret value into the keys it holds.
+///
+/// The value is either a single OpenSSH private key or a YAML/JSON document
+/// with a `keys` list (see `docs/SECRET_FORMAT.md`).
+pub fn parse_secret_value(value: &str) -> Result<Vec<KeyEntry>> {
+    if value.trim_start().starts_with("-----BEGIN") {
+        let private_key = PrivateKey::from_openssh(value)?;
+        if private_key.is_encrypted() {
+            bail!("Private key is encrypted, store it in a key bundle with its passphrase");
+        }
+        return Ok(vec![KeyEntry {
+            private_key,
+            certificate: None,
+            comment: None,
+            constraints: KeyConstraints::default(),
+        }]);
+    }
+
+    let bundle: KeyBundle = serde_yaml::from_str(value).map_err(|e| {
+        anyhow!(
+            "Secret value is neither an OpenSSH private key nor a valid key bundle: {}",
+            e
+        )
+    })?;
+    if bundle.keys.is_empty() {
+        bail!("Key bundle does not contain any key");
+    }
+
+    bundle
+        .keys
+        .into_iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            parse_bundled_key(entry).with_context(|| format!("Invalid key #{} in bundle", position))
+        })
+        .collect()
+}
+
+fn parse_bundled_key(entry: BundledKey) -> Result<KeyEntry> {
+    if entry
+        .constraints
+        .namespaces
+        .as_ref()
+        .is_some_and(Vec::is_empty)
+    {
+        bail!("constraints.namespaces must list at least one namespace");
+    }
+    let mut private_key = PrivateKey::from_openssh(entry.key.as_str())?;
+    if private_key.is_encrypted() {
+        let passphrase = entry
+            .passphrase
+            .as_ref()
+            .ok_or_else(|| anyhow!("Private key is encrypted but no passphrase is set"))?;
+        private_key = private_key
+            .decrypt(passphrase.as_bytes())
+            .map_err(|_| anyhow!("Failed to decrypt private key, wrong passphrase?"))?;
+    }
+
+    let certificate = entry
+        .certificate
+        .as_deref()
+        .map(|certificate| {
+            let certificate = Certificate::from_openssh(certificate.trim())
+                .context("Failed to parse certificate")?;
+            if certificate.public_key() != private_key.public_key().key_data() {
+                bail!("Certificate does not match the private key");
+            }
+            Ok(certificate)
+        })
+        .transpose()?;
+
+    Ok(KeyEntry {
+        private_key,
+        certificate,
+        comment: entry.comment,
+        constraints: entry.constraints,
+    })
+}
+
+/// Read the option block of a secret note.
+///
+/// The block starts at a `[vault-conductor]` line and ends at the next
+/// `[section]` line or at the end of the note. Its content is YAML:
+///
+/// ```text
+/// Deploy key for the production cluster
+///
+/// [vault-conductor]
+/// comment: deploy@prod
+/// confirm: true
+/// lifetime: 3600
+/// ```
+pub fn parse_note_options(note: &str) -> Result<NoteOptions> {
+    let mut lines = note
+        .lines()
+        .skip_while(|line| line.trim() != NOTE_OPTIONS_HEADER);
+    if lines.next().is_none() {
+        return Ok(NoteOptions::default());
+    }
+    let block = lines
+        .take_while(|line| !is_section_header(line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if block.trim().is_empty() {
+        return Ok(NoteOptions::default());
+    }
+
+    let options: NoteOptions = serde_yaml::from_str(&block).map_err(|e| {
+        anyhow!(
+            "Invalid {} block in secret note: {}",
+            NOTE_OPTIONS_HEADER,
+            e
+        )
+    })?;
+    if options.namespaces.as_ref().is_some_and(Vec::is_empty) {
+        bail!(
+            "Invalid {} block in secret note: namespaces must list at least one namespace",
+            NOTE_OPTIONS_HEADER
+        );
+    }
+    Ok(options)
+}
+
+fn is_section_header(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with('[') && line.ends_with(']') && !line.contains(':')
+}
@@ -1,2 +1,14 @@
 pub mod agent;
-pub mod client_wrapper;
+pub mod comment;
+pub mod confirm;
+pub mod connection;
+pub mod daemon;
+pub mod keyring;
+pub mod policy;
+pub mod refresh;
+pub mod server;
+pub mod shutdown;
+pub mod upstream;
+
+#[cfg(all(test, unix))]
+pub(crate) mod tests;
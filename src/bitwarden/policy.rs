@@ -0,0 +1,105 @@
+use crate::config::KeyPolicy;
+use anyhow::{bail, Result};
+use ssh_key::{Algorithm, HashAlg, PublicKey};
+
+/// Sign request flag asking for an `rsa-sha2-256` signature
+pub const SSH_AGENT_RSA_SHA2_256: u32 = 0x02;
+/// Sign request flag asking for an `rsa-sha2-512` signature
+pub const SSH_AGENT_RSA_SHA2_512: u32 = 0x04;
+
+impl KeyPolicy {
+    /// Check that a key is strong enough and of an allowed algorithm
+    pub fn check_key(&self, key: &PublicKey) -> Result<()> {
+        let algorithm = key.algorithm();
+
+        if let Some(allowed) = &self.allowed_algorithms {
+            let is_rsa = key.key_data().rsa().is_some();
+            // `rsa-sha2-256` and `rsa-sha2-512` allow RSA keys, their hash is
+            // enforced when signing
+            let is_allowed = allowed
+                .iter()
+                .filter_map(|name| Algorithm::new(name).ok())
+                .any(|allowed| allowed == algorithm || (allowed.is_rsa() && is_rsa));
+            if !is_allowed {
+                bail!(
+                    "algorithm {} is not in the allowed algorithms ({})",
+                    algorithm,
+                    allowed.join(", ")
+                );
+            }
+        }
+
+        if let Some(rsa) = key.key_data().rsa() {
+            let bits = rsa
+                .n
+                .as_positive_bytes()
+                .and_then(|bytes| {
+                    let leading = bytes.first()?.leading_zeros() as usize;
+                    Some(bytes.len() * 8 - leading)
+                })
+                .unwrap_or(0);
+            if bits < self.min_rsa_bits as usize {
+                bail!(
+                    "RSA key size {} bits is below the minimum of {} bits",
+                    bits,
+                    self.min_rsa_bits
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hash to sign with for a key, honoring the flags of the sign request.
+    /// Returns `None` for algorithms where the hash is implied by the key type.
+    pub fn signature_hash(&self, algorithm: Algorithm, flags: u32) -> Result<Option<HashAlg>> {
+        if !algorithm.is_rsa() {
+            return Ok(None);
+        }
+        let hash = if flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+            Some(HashAlg::Sha512)
+        } else if flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+            Some(HashAlg::Sha256)
+        } else if self.allow_sha1 {
+            // plain `ssh-rsa` signature
+            None
+        } else {
+            bail!(
+                "Refusing to create a SHA-1 (ssh-rsa) signature, \
+                the client must request rsa-sha2-256 or rsa-sha2-512"
+            )
+        };
+
+        if let Some(allowed) = self.allowed_rsa_hashes() {
+            if !allowed.contains(&hash) {
+                let requested = Algorithm::Rsa { hash };
+                bail!(
+                    "Refusing to create an {} signature, it is not in the allowed algorithms ({})",
+                    requested,
+                    self.allowed_algorithms
+                        .as_deref()
+                        .unwrap_or_default()
+                        .join(", ")
+                );
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Hashes RSA signatures may use when `allowed_algorithms` only lists
+    /// `rsa-sha2-256` or `rsa-sha2-512`, any if it lists `ssh-rsa` or is not set
+    fn allowed_rsa_hashes(&self) -> Option<Vec<Option<HashAlg>>> {
+        let allowed = self.allowed_algorithms.as_ref()?;
+        let hashes: Vec<Option<HashAlg>> = allowed
+            .iter()
+            .filter_map(|name| match Algorithm::new(name) {
+                Ok(Algorithm::Rsa { hash }) => Some(hash),
+                _ => None,
+            })
+            .collect();
+        if hashes.contains(&None) {
+            return None;
+        }
+        Some(hashes)
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::time::{interval_at, Instant, Interval};
+
+use crate::bitwarden::agent::{BitwardenAgent, SecretFetcher, SecretId};
+
+/// Keeps the served keys in sync with the backend: picks up rotated keys, and the
+/// secrets added to or removed from the watched projects
+pub struct Refresher<F: SecretFetcher + Clone> {
+    fetcher: Arc<F>,
+    /// Agent holding the keys of every socket, refreshed in place
+    agent: BitwardenAgent<F>,
+    /// View of the default socket, which serves the secrets of the watched projects
+    default_view: BitwardenAgent<F>,
+    /// Secrets served on the default socket whatever the projects hold
+    configured: Vec<SecretId>,
+    watch_projects: bool,
+}
+
+impl<F: SecretFetcher + Clone> Refresher<F> {
+    pub fn new(
+        fetcher: Arc<F>,
+        agent: BitwardenAgent<F>,
+        default_view: BitwardenAgent<F>,
+        configured: Vec<SecretId>,
+    ) -> Self {
+        Self {
+            fetcher,
+            agent,
+            default_view,
+            configured,
+            watch_projects: false,
+        }
+    }
+
+    /// Serve the secrets discovered in the configured projects as they change
+    pub fn with_watched_projects(mut self, watch_projects: bool) -> Self {
+        self.watch_projects = watch_projects;
+        self
+    }
+
+    /// Sync the watched projects, then fetch the cached keys again
+    pub async fn refresh(&self) {
+        if self.watch_projects {
+            self.sync_projects().await;
+        }
+        let rotated = self.agent.refresh().await;
+        info!("Keys refreshed, {} secret(s) rotated", rotated.len());
+    }
+
+    /// Serve the secrets currently in the watched projects on the default socket
+    async fn sync_projects(&self) {
+        let discovered = match self.fetcher.discover_secrets().await {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!(
+                    "Failed to list the secrets of the watched projects, \
+                    serving the same secrets: {:#}",
+                    e
+                );
+                return;
+            }
+        };
+        let mut ids = self.configured.clone();
+        for id in discovered {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        let served = self.default_view.served_secrets();
+        let added: Vec<&SecretId> = ids.iter().filter(|id| !served.contains(id)).collect();
+        let removed: Vec<&SecretId> = served.iter().filter(|id| !ids.contains(id)).collect();
+        for id in &added {
+            info!("Secret {} added to a watched project, serving it", id);
+        }
+        for id in &removed {
+            info!(
+                "Secret {} removed from the watched projects, no longer serving it",
+                id
+            );
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            self.default_view.serve_secrets(&ids);
+        }
+    }
+}
+
+/// Signals sent by `vault-conductor refresh` and `vault-conductor reset-counters`
+pub struct OperatorSignals {
+    sighup: Signal,
+    sigusr1: Signal,
+}
+
+impl OperatorSignals {
+    /// Handle the signals from now on. Install them before the slow parts of the
+    /// startup: until then, the default action of these signals kills the agent.
+    pub fn install() -> Result<Self> {
+        Ok(Self {
+            sighup: signal(SignalKind::hangup())?,
+            sigusr1: signal(SignalKind::user_defined1())?,
+        })
+    }
+}
+
+/// Refresh the keys every `interval`, if set, and whenever the agent receives SIGHUP.
+/// SIGUSR1 resets the signature counters of the keys capped by `max_signatures`.
+/// Signals received before are handled once it runs.
+pub async fn run<F: SecretFetcher + Clone>(
+    refresher: Refresher<F>,
+    interval: Option<Duration>,
+    signals: OperatorSignals,
+) {
+    let OperatorSignals {
+        mut sighup,
+        mut sigusr1,
+    } = signals;
+    let mut ticker = interval.map(|period| interval_at(Instant::now() + period, period));
+    loop {
+        tokio::select! {
+            _ = sighup.recv() => info!("Received SIGHUP, refreshing keys"),
+            _ = tick(&mut ticker) => debug!("Refreshing keys"),
+            _ = sigusr1.recv() => {
+                info!("Received SIGUSR1, resetting signature counters");
+                refresher.agent.reset_signature_counters();
+                continue;
+            }
+        }
+        refresher.refresh().await;
+    }
+}
+
+/// Next tick of `ticker`, never without one
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,127 @@
+use crate::backends;
+use crate::bitwarden::connection::ConnectionContext;
+use crate::bitwarden::daemon::{add_discovered_secrets, build_agent, served_secret_ids};
+use crate::bitwarden::upstream::{ProxyAgent, UnixSocketUpstream};
+use crate::config::Config;
+use crate::secure_memory::disable_core_dumps;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::proto::{Request, Response};
+use ssh_encoding::{Decode, Encode};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use zeroize::Zeroizing;
+
+/// Largest request accepted, the same limit as OpenSSH's agent
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+/// Answer the agent requests read from `reader` on `writer` until `reader` is closed.
+/// Each message is framed by its length as a big-endian u32, as on an agent socket.
+pub async fn serve_session<S, R, W>(session: &mut S, mut reader: R, mut writer: W) -> Result<()>
+where
+    S: Session,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                debug!("Client closed the session");
+                return Ok(());
+            }
+            Err(e) => return Err(e).context("Failed to read agent request"),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_LEN {
+            bail!(
+                "Agent request of {} bytes exceeds the limit of {} bytes",
+                length,
+                MAX_MESSAGE_LEN
+            );
+        }
+
+        // Requests may carry the passphrase of a lock request
+        let mut message = Zeroizing::new(vec![0u8; length]);
+        reader
+            .read_exact(&mut message)
+            .await
+            .context("Failed to read agent request, truncated message")?;
+
+        let response = match Request::decode(&mut message.as_slice()) {
+            Ok(request) => session.handle(request).await.unwrap_or_else(|e| {
+                warn!("Failed to handle agent request: {}", e);
+                Response::Failure
+            }),
+            Err(e) => {
+                warn!("Unsupported agent request: {}", e);
+                Response::Failure
+            }
+        };
+
+        writer
+            .write_all(&encode_response(&response)?)
+            .await
+            .context("Failed to write agent response")?;
+        writer
+            .flush()
+            .await
+            .context("Failed to write agent response")?;
+    }
+}
+
+/// Encode `response` prefixed with its length
+fn encode_response(response: &Response) -> Result<Vec<u8>> {
+    let length = response
+        .encoded_len()
+        .context("Failed to encode agent response")?;
+    let mut frame = Vec::with_capacity(4 + length);
+    (length as u32)
+        .encode(&mut frame)
+        .context("Failed to encode agent response")?;
+    response
+        .encode(&mut frame)
+        .context("Failed to encode agent response")?;
+    Ok(frame)
+}
+
+/// Serve a single agent session on stdin/stdout, e.g. behind `socat` or `docker exec -i`
+pub async fn serve_stdio(config_file: Option<String>, profile: Option<String>) -> Result<()> {
+    // Keep key material out of core dumps before any secret is loaded
+    disable_core_dumps();
+
+    let mut config = Config::load(&config_file).context("Failed to load configuration")?;
+    let fetcher = Arc::new(backends::connect(&mut config).await?);
+    add_discovered_secrets(&mut config, fetcher.as_ref()).await;
+    let secret_ids = served_secret_ids(&config, profile.as_deref())?;
+
+    let agent = build_agent(&config, fetcher);
+    let connection = ConnectionContext::stdio();
+    info!(
+        "[{}] Serving {} secret(s) on stdin/stdout for: {}",
+        connection,
+        secret_ids.len(),
+        connection
+            .peer
+            .as_ref()
+            .and_then(|peer| peer.command.as_deref())
+            .unwrap_or("unknown command")
+    );
+    let mut session = agent.view(&secret_ids)?.with_connection(connection);
+
+    // As on the sockets, upstream keys are only merged when no profile is selected
+    let result = match config.upstream_agent.as_ref().filter(|_| profile.is_none()) {
+        Some(path) => {
+            info!("Merging identities from upstream agent at {}", path);
+            let mut proxy = ProxyAgent::new(session, UnixSocketUpstream::new(PathBuf::from(path)));
+            serve_session(&mut proxy, tokio::io::stdin(), tokio::io::stdout()).await
+        }
+        None => serve_session(&mut session, tokio::io::stdin(), tokio::io::stdout()).await,
+    };
+
+    agent.clear_cache();
+    result
+}
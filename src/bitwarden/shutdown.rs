@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use log::debug;
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Extension, Identity, SignRequest};
+use ssh_key::Signature;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Requests being handled by the agent sessions, so that shutdown can wait for them
+#[derive(Default)]
+pub struct RequestTracker {
+    in_flight: AtomicUsize,
+    draining: AtomicBool,
+    idle: Notify,
+}
+
+impl RequestTracker {
+    /// Register a request, `None` once the agent is shutting down
+    pub fn begin(self: &Arc<Self>) -> Option<RequestGuard> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = RequestGuard(self.clone());
+        if self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(guard)
+    }
+
+    /// Number of requests being handled
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new requests and wait up to `timeout` for the ones in flight.
+    /// Returns whether every request finished in time.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::SeqCst);
+        tokio::time::timeout(timeout, async {
+            loop {
+                let idle = self.idle.notified();
+                tokio::pin!(idle);
+                // Register for the notification before checking, not to miss it
+                idle.as_mut().enable();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+/// Marks a request as in flight until dropped
+pub struct RequestGuard(Arc<RequestTracker>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Session whose requests are tracked, and refused once the agent is shutting down
+pub struct TrackedSession<S: Session> {
+    inner: S,
+    requests: Arc<RequestTracker>,
+}
+
+impl<S: Session> TrackedSession<S> {
+    pub fn new(inner: S, requests: Arc<RequestTracker>) -> Self {
+        Self { inner, requests }
+    }
+
+    fn begin(&self) -> Result<RequestGuard, AgentError> {
+        self.requests.begin().ok_or_else(|| {
+            debug!("Refusing request, the agent is shutting down");
+            AgentError::other(Box::new(std::io::Error::other("Agent is shutting down")))
+        })
+    }
+}
+
+#[async_trait]
+impl<S: Session> Session for TrackedSession<S> {
+    async fn request_identities(&mut self) -> Result<Vec<Identity>, AgentError> {
+        let _request = self.begin()?;
+        self.inner.request_identities().await
+    }
+
+    async fn sign(&mut self, request: SignRequest) -> Result<Signature, AgentError> {
+        let _request = self.begin()?;
+        self.inner.sign(request).await
+    }
+
+    async fn lock(&mut self, key: String) -> Result<(), AgentError> {
+        let _request = self.begin()?;
+        self.inner.lock(key).await
+    }
+
+    async fn unlock(&mut self, key: String) -> Result<(), AgentError> {
+        let _request = self.begin()?;
+        self.inner.unlock(key).await
+    }
+
+    async fn extension(&mut self, extension: Extension) -> Result<Option<Extension>, AgentError> {
+        let _request = self.begin()?;
+        self.inner.extension(extension).await
+    }
+}
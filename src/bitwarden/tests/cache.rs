@@ -0,0 +1,69 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+
+    use signature::Verifier;
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{PublicCredential, SignRequest};
+    use std::sync::Arc;
+
+    fn agent(fetcher: &MockFetcher) -> BitwardenAgent<MockFetcher> {
+        BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1), secret_id(2)])
+    }
+
+    fn fetcher() -> MockFetcher {
+        MockFetcher::default()
+            .with_secret(secret_id(1), "ed25519", ED25519_KEY)
+            .with_secret(secret_id(2), "rsa", RSA_2048_KEY)
+    }
+
+    #[tokio::test]
+    async fn keys_are_fetched_once_and_cached() {
+        let fetcher = fetcher();
+        let mut agent = agent(&fetcher);
+
+        let identities = agent.request_identities().await.unwrap();
+        agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 2);
+        assert_eq!(identities[0].comment, "ed25519");
+        assert_eq!(identities[1].comment, "rsa");
+        assert_eq!(fetcher.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn cached_keys_still_sign() {
+        let fetcher = fetcher();
+        let mut agent = agent(&fetcher);
+
+        let identity = agent.request_identities().await.unwrap().remove(0);
+        let signature = agent
+            .sign(SignRequest {
+                credential: identity.credential.clone(),
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await
+            .unwrap();
+
+        let PublicCredential::Key(key_data) = identity.credential else {
+            panic!("expected a plain public key");
+        };
+        key_data
+            .verify(b"data to sign", &signature)
+            .expect("signature should verify with the advertised key");
+    }
+
+    #[tokio::test]
+    async fn cleared_cache_is_fetched_again() {
+        let fetcher = fetcher();
+        let mut agent = agent(&fetcher);
+
+        agent.request_identities().await.unwrap();
+        agent.clear_cache();
+        agent.request_identities().await.unwrap();
+
+        assert_eq!(fetcher.fetches(), 4);
+    }
+}
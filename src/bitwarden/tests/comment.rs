@@ -0,0 +1,68 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::comment::validate_template;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY};
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_key::{HashAlg, PrivateKey};
+    use std::sync::Arc;
+
+    fn fetcher() -> MockFetcher {
+        MockFetcher::default()
+            .with_secret(secret_id(1), "deploy", ED25519_KEY)
+            .with_project(secret_id(1), "infra")
+    }
+
+    #[tokio::test]
+    async fn template_is_rendered_for_each_key() {
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher()), vec![secret_id(1)])
+            .with_comment_template(Some(
+                "{name} ({project}) {algorithm} [{fingerprint_short}] {uuid}".to_string(),
+            ));
+
+        let identities = agent.request_identities().await.unwrap();
+
+        let fingerprint = PrivateKey::from_openssh(ED25519_KEY)
+            .unwrap()
+            .fingerprint(HashAlg::Sha256)
+            .to_string();
+        assert_eq!(
+            identities[0].comment,
+            format!(
+                "deploy (infra) ssh-ed25519 [{}] {}",
+                &fingerprint["SHA256:".len().."SHA256:".len() + 8],
+                secret_id(1)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn unnamed_secret_falls_back_to_its_uuid() {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "", ED25519_KEY);
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)]);
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities[0].comment, format!("vc:{}", secret_id(1)));
+    }
+
+    #[tokio::test]
+    async fn explicit_comment_wins_over_template() {
+        let fetcher = fetcher().with_note(secret_id(1), "[vault-conductor]\ncomment: explicit");
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)])
+            .with_comment_template(Some("vc:{uuid}".to_string()));
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities[0].comment, "explicit");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert!(validate_template("{name} [{fingerprint_short}]").is_ok());
+        assert!(validate_template("{hostname}").is_err());
+        assert!(validate_template("{name").is_err());
+        assert!(validate_template("name}").is_err());
+    }
+}
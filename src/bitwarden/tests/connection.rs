@@ -0,0 +1,47 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::connection::{ConnectionContext, PeerInfo};
+
+    use tokio::net::UnixStream;
+
+    #[test]
+    fn context_without_peer_shows_its_id() {
+        let connection = ConnectionContext { id: 7, peer: None };
+
+        assert_eq!(connection.to_string(), "conn 7");
+        assert_eq!(connection.requester(), None);
+    }
+
+    #[test]
+    fn context_shows_the_peer_program() {
+        let connection = ConnectionContext {
+            id: 3,
+            peer: Some(PeerInfo {
+                pid: Some(1234),
+                uid: 1000,
+                command: Some("/usr/bin/ssh -T git@github.com".to_string()),
+            }),
+        };
+
+        assert_eq!(connection.to_string(), "conn 3 pid 1234 uid 1000 ssh");
+        assert_eq!(connection.requester().as_deref(), Some("ssh (pid 1234)"));
+    }
+
+    #[tokio::test]
+    async fn peer_of_a_socket_is_captured() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+
+        let connection = ConnectionContext::from_stream(1, &stream);
+
+        let peer = connection
+            .peer
+            .expect("peer credentials should be readable");
+        // SAFETY: getuid has no preconditions and cannot fail
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(peer.pid, Some(std::process::id() as i32));
+            assert!(peer.command.is_some());
+        }
+    }
+}
@@ -0,0 +1,51 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::daemon::add_discovered_secrets;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher};
+    use crate::config::Config;
+
+    fn config(secret_ids: &[u128]) -> Config {
+        let mut config: Config = serde_yaml::from_str(
+            "bws_access_token: token\nbw_project_ids: ['00000000-0000-0000-0000-0000000000aa']\n",
+        )
+        .unwrap();
+        config.bw_secret_ids = secret_ids
+            .iter()
+            .map(|n| secret_id(*n).to_string())
+            .collect();
+        config
+    }
+
+    #[tokio::test]
+    async fn discovered_secrets_are_added_once() {
+        let mut config = config(&[1]);
+        let fetcher = MockFetcher::default().with_discovered(&[secret_id(1), secret_id(2)]);
+
+        add_discovered_secrets(&mut config, &fetcher).await;
+
+        assert_eq!(
+            config.bw_secret_ids,
+            vec![secret_id(1).to_string(), secret_id(2).to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_discovery_keeps_configured_secrets() {
+        let mut config = config(&[1]);
+
+        add_discovered_secrets(&mut config, &MockFetcher::default()).await;
+
+        assert_eq!(config.bw_secret_ids, vec![secret_id(1).to_string()]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_discovered_without_projects() {
+        let mut config = config(&[1]);
+        config.bw_project_ids.clear();
+        let fetcher = MockFetcher::default().with_discovered(&[secret_id(2)]);
+
+        add_discovered_secrets(&mut config, &fetcher).await;
+
+        assert_eq!(config.bw_secret_ids, vec![secret_id(1).to_string()]);
+    }
+}
@@ -0,0 +1,189 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+    use crate::config::KeyOptions;
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{Identity, SignRequest};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn fetcher() -> MockFetcher {
+        MockFetcher::default()
+            .with_secret(secret_id(1), "primary", ED25519_KEY)
+            .with_secret(secret_id(2), "rsa", RSA_2048_KEY)
+            .with_secret(secret_id(3), "copy", ED25519_KEY)
+    }
+
+    fn agent(fetcher: &MockFetcher) -> BitwardenAgent<MockFetcher> {
+        BitwardenAgent::new(
+            Arc::new(fetcher.clone()),
+            vec![secret_id(1), secret_id(2), secret_id(3)],
+        )
+    }
+
+    /// Agent where the primary and the copy of the ed25519 key have their own options
+    fn agent_with_options(primary: KeyOptions, copy: KeyOptions) -> BitwardenAgent<MockFetcher> {
+        let options = HashMap::from([(secret_id(1), primary), (secret_id(3), copy)]);
+        agent(&fetcher()).with_key_options(&options)
+    }
+
+    fn sign_request(identity: &Identity, data: &[u8]) -> SignRequest {
+        SignRequest {
+            credential: identity.credential.clone(),
+            data: data.to_vec(),
+            flags: 0,
+        }
+    }
+
+    /// Start of the blob `ssh-keygen -Y sign -n <namespace>` asks the agent to sign
+    fn sshsig_blob(namespace: &str) -> Vec<u8> {
+        let mut blob = b"SSHSIG".to_vec();
+        blob.extend_from_slice(&(namespace.len() as u32).to_be_bytes());
+        blob.extend_from_slice(namespace.as_bytes());
+        blob.extend_from_slice(&[0, 0, 0, 0]);
+        blob
+    }
+
+    #[tokio::test]
+    async fn duplicate_key_is_advertised_once() {
+        let mut agent = agent(&fetcher());
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 2);
+        assert_eq!(identities[0].comment, "primary");
+        assert_eq!(identities[1].comment, "rsa");
+    }
+
+    #[tokio::test]
+    async fn duplicate_is_used_when_primary_is_inaccessible() {
+        let fetcher = fetcher();
+        let mut agent = agent(&fetcher);
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        agent.clear_cache();
+        fetcher.make_unavailable(secret_id(1));
+
+        let identities = agent.request_identities().await.unwrap();
+        assert_eq!(identities.len(), 2);
+        assert_eq!(identities[1].comment, "copy");
+        agent
+            .sign(SignRequest {
+                credential: identity.credential,
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await
+            .expect("the copy should sign in place of the primary");
+    }
+
+    #[tokio::test]
+    async fn duplicate_does_not_bypass_signature_limit() {
+        let options = HashMap::from([(
+            secret_id(1),
+            KeyOptions {
+                max_signatures: Some(1),
+                ..Default::default()
+            },
+        )]);
+        let mut agent = agent(&fetcher()).with_key_options(&options);
+        let identity = agent.request_identities().await.unwrap().remove(0);
+        let request = SignRequest {
+            credential: identity.credential,
+            data: b"data to sign".to_vec(),
+            flags: 0,
+        };
+
+        agent.sign(request.clone()).await.unwrap();
+
+        assert!(agent.sign(request).await.is_err());
+        assert_eq!(agent.request_identities().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn key_hidden_in_a_copy_is_not_advertised() {
+        let mut agent = agent_with_options(
+            KeyOptions::default(),
+            KeyOptions {
+                hidden: Some(true),
+                ..Default::default()
+            },
+        );
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].comment, "rsa");
+    }
+
+    #[tokio::test]
+    async fn confirmation_asked_by_a_copy_applies() {
+        // Every confirmation is refused
+        std::env::set_var("SSH_ASKPASS", "false");
+        let mut agent = agent_with_options(
+            KeyOptions::default(),
+            KeyOptions {
+                confirm: Some(true),
+                ..Default::default()
+            },
+        );
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        let error = agent
+            .sign(sign_request(&identity, b"data to sign"))
+            .await
+            .expect_err("the copy asks for a confirmation");
+        assert!(error.to_string().contains("Key use not confirmed"));
+    }
+
+    #[tokio::test]
+    async fn namespaces_of_copies_are_intersected() {
+        let mut agent = agent_with_options(
+            KeyOptions {
+                namespaces: Some(vec!["git".to_string(), "file".to_string()]),
+                ..Default::default()
+            },
+            KeyOptions {
+                namespaces: Some(vec!["git".to_string()]),
+                ..Default::default()
+            },
+        );
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        assert!(agent
+            .sign(sign_request(&identity, &sshsig_blob("git")))
+            .await
+            .is_ok());
+        assert!(agent
+            .sign(sign_request(&identity, &sshsig_blob("file")))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn smallest_signature_limit_of_copies_applies() {
+        let mut agent = agent_with_options(
+            KeyOptions {
+                max_signatures: Some(3),
+                ..Default::default()
+            },
+            KeyOptions {
+                max_signatures: Some(1),
+                ..Default::default()
+            },
+        );
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        assert!(agent
+            .sign(sign_request(&identity, b"data to sign"))
+            .await
+            .is_ok());
+        assert!(agent
+            .sign(sign_request(&identity, b"data to sign"))
+            .await
+            .is_err());
+        assert_eq!(agent.request_identities().await.unwrap().len(), 1);
+    }
+}
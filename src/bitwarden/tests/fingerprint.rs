@@ -0,0 +1,87 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{
+        bundle, secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY,
+    };
+    use crate::config::KeyOptions;
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::PublicCredential;
+    use ssh_key::{Algorithm, HashAlg, PrivateKey};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn agent_pinned_to(fingerprint: String) -> BitwardenAgent<MockFetcher> {
+        agent_for(ED25519_KEY, fingerprint)
+    }
+
+    fn agent_for(value: &str, fingerprint: String) -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "ed25519", value);
+        let options = HashMap::from([(
+            secret_id(1),
+            KeyOptions {
+                fingerprint: Some(fingerprint),
+                ..Default::default()
+            },
+        )]);
+        BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)]).with_key_options(&options)
+    }
+
+    fn fingerprint_of(key: &str) -> String {
+        PrivateKey::from_openssh(key)
+            .unwrap()
+            .fingerprint(HashAlg::Sha256)
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn matching_fingerprint_is_loaded() {
+        let mut agent = agent_pinned_to(fingerprint_of(ED25519_KEY));
+
+        assert_eq!(agent.request_identities().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn swapped_key_is_refused() {
+        let mut agent = agent_pinned_to(fingerprint_of(RSA_2048_KEY));
+
+        assert!(agent.request_identities().await.unwrap().is_empty());
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("swapped key should fail");
+        assert!(error.contains("Fingerprint mismatch"));
+    }
+
+    #[tokio::test]
+    async fn only_the_pinned_key_of_a_bundle_is_served() {
+        let value = bundle(&[RSA_2048_KEY, ED25519_KEY]);
+        let mut agent = agent_for(&value, fingerprint_of(ED25519_KEY));
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert!(matches!(
+            &identities[0].credential,
+            PublicCredential::Key(key) if key.algorithm() == Algorithm::Ed25519
+        ));
+    }
+
+    #[tokio::test]
+    async fn bundle_without_pinned_key_is_refused() {
+        let value = bundle(&[RSA_2048_KEY]);
+        let mut agent = agent_for(&value, fingerprint_of(ED25519_KEY));
+
+        assert!(agent.request_identities().await.unwrap().is_empty());
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("bundle without the pinned key should fail");
+        assert!(error.contains("Fingerprint mismatch"));
+    }
+}
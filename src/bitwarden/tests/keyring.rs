@@ -0,0 +1,167 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{
+        block, secret_id, MockFetcher, ED25519_CERT, ED25519_KEY, ENCRYPTED_ED25519_KEY,
+        RSA_2048_KEY,
+    };
+
+    use signature::Verifier;
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{PublicCredential, SignRequest};
+    use std::sync::Arc;
+
+    fn agent_for(bundle: &str) -> (MockFetcher, BitwardenAgent<MockFetcher>) {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "keyring", bundle);
+        let agent = BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1)]);
+        (fetcher, agent)
+    }
+
+    #[tokio::test]
+    async fn bundle_expands_into_several_identities() {
+        let bundle = format!(
+            "keys:\n  - comment: deploy\n    key: |\n{}  - key: |\n{}",
+            block(ED25519_KEY),
+            block(RSA_2048_KEY)
+        );
+        let (_, mut agent) = agent_for(&bundle);
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 2);
+        assert_eq!(identities[0].comment, "deploy");
+        // Keys without a comment fall back to the secret name
+        assert_eq!(identities[1].comment, "keyring");
+    }
+
+    #[tokio::test]
+    async fn encrypted_key_is_decrypted_with_its_passphrase() {
+        let bundle = format!(
+            "keys:\n  - passphrase: test-passphrase\n    key: |\n{}",
+            block(ENCRYPTED_ED25519_KEY)
+        );
+        let (_, mut agent) = agent_for(&bundle);
+
+        let identity = agent.request_identities().await.unwrap().remove(0);
+        let signature = agent
+            .sign(SignRequest {
+                credential: identity.credential.clone(),
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await
+            .unwrap();
+
+        let PublicCredential::Key(key_data) = identity.credential else {
+            panic!("expected a plain public key");
+        };
+        key_data.verify(b"data to sign", &signature).unwrap();
+    }
+
+    #[tokio::test]
+    async fn wrong_passphrase_is_reported() {
+        let bundle = format!(
+            "keys:\n  - passphrase: not-the-passphrase\n    key: |\n{}",
+            block(ENCRYPTED_ED25519_KEY)
+        );
+        let (_, agent) = agent_for(&bundle);
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("decryption should fail");
+        assert!(error.contains("wrong passphrase"));
+    }
+
+    #[tokio::test]
+    async fn empty_namespaces_are_rejected() {
+        let bundle = format!(
+            "keys:\n  - constraints:\n      namespaces: []\n    key: |\n{}",
+            block(ED25519_KEY)
+        );
+        let (_, agent) = agent_for(&bundle);
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("an empty namespace list should fail");
+        assert!(error.contains("namespaces must list at least one namespace"));
+    }
+
+    #[tokio::test]
+    async fn certificate_is_advertised_and_signs() {
+        let bundle = format!(
+            "keys:\n  - certificate: {}\n    key: |\n{}",
+            ED25519_CERT,
+            block(ED25519_KEY)
+        );
+        let (_, mut agent) = agent_for(&bundle);
+
+        let identities = agent.request_identities().await.unwrap();
+        assert_eq!(identities.len(), 2);
+        let PublicCredential::Cert(certificate) = &identities[0].credential else {
+            panic!("expected the certificate first");
+        };
+        assert_eq!(certificate.key_id(), "test-cert");
+
+        let signature = agent
+            .sign(SignRequest {
+                credential: identities[0].credential.clone(),
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await
+            .unwrap();
+        certificate
+            .public_key()
+            .verify(b"data to sign", &signature)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn certificate_for_another_key_is_rejected() {
+        let bundle = format!(
+            "keys:\n  - certificate: {}\n    key: |\n{}",
+            ED25519_CERT,
+            block(RSA_2048_KEY)
+        );
+        let (_, agent) = agent_for(&bundle);
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("certificate should not match");
+        assert!(error.contains("Certificate does not match"));
+    }
+
+    #[tokio::test]
+    async fn expired_lifetime_fetches_the_secret_again() {
+        let bundle = format!(
+            "keys:\n  - constraints:\n      lifetime: 0\n    key: |\n{}",
+            block(ED25519_KEY)
+        );
+        let (fetcher, mut agent) = agent_for(&bundle);
+
+        agent.request_identities().await.unwrap();
+        agent.request_identities().await.unwrap();
+
+        assert_eq!(fetcher.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn json_bundle_is_accepted() {
+        let bundle = format!(
+            "{{\"keys\": [{{\"key\": \"{}\", \"comment\": \"json\"}}]}}",
+            ED25519_KEY.replace('\n', "\\n")
+        );
+        let (_, mut agent) = agent_for(&bundle);
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].comment, "json");
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY};
+    use crate::config::KeyOptions;
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{Identity, SignRequest};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn one_shot_agent() -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "break-glass", ED25519_KEY);
+        one_shot_agent_with(fetcher, KeyOptions::default())
+    }
+
+    fn one_shot_agent_with(
+        fetcher: MockFetcher,
+        options: KeyOptions,
+    ) -> BitwardenAgent<MockFetcher> {
+        let options = HashMap::from([(
+            secret_id(1),
+            KeyOptions {
+                max_signatures: Some(1),
+                ..options
+            },
+        )]);
+        BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)]).with_key_options(&options)
+    }
+
+    fn sign_request(identity: &Identity) -> SignRequest {
+        SignRequest {
+            credential: identity.credential.clone(),
+            data: b"data to sign".to_vec(),
+            flags: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn one_shot_key_signs_once_then_disappears() {
+        let mut agent = one_shot_agent();
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        assert!(agent.sign(sign_request(&identity)).await.is_ok());
+        assert!(agent.sign(sign_request(&identity)).await.is_err());
+        assert!(agent.request_identities().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_signature_does_not_use_up_the_allowance() {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "break-glass", ED25519_KEY);
+        // Keys expire at once, so that signing fetches the secret again
+        let mut agent = one_shot_agent_with(
+            fetcher.clone(),
+            KeyOptions {
+                lifetime: Some(0),
+                ..Default::default()
+            },
+        );
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        // The fetch of the private key, after the one finding the key, fails
+        fetcher.fail_fetch(2);
+        assert!(agent.sign(sign_request(&identity)).await.is_err());
+
+        assert!(agent.sign(sign_request(&identity)).await.is_ok());
+        assert!(agent.sign(sign_request(&identity)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unlocking_keeps_signature_counters() {
+        let mut agent = one_shot_agent();
+        let identity = agent.request_identities().await.unwrap().remove(0);
+        agent.sign(sign_request(&identity)).await.unwrap();
+
+        agent.lock("passphrase".to_string()).await.unwrap();
+        assert!(agent.request_identities().await.unwrap().is_empty());
+        assert!(agent.unlock("wrong".to_string()).await.is_err());
+        agent.unlock("passphrase".to_string()).await.unwrap();
+
+        assert!(agent.request_identities().await.unwrap().is_empty());
+        assert!(agent.sign(sign_request(&identity)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn operator_reset_starts_a_new_allowance() {
+        let mut agent = one_shot_agent();
+        let identity = agent.request_identities().await.unwrap().remove(0);
+        agent.sign(sign_request(&identity)).await.unwrap();
+
+        agent.reset_signature_counters();
+
+        assert_eq!(agent.request_identities().await.unwrap().len(), 1);
+        assert!(agent.sign(sign_request(&identity)).await.is_ok());
+    }
+}
@@ -0,0 +1,16 @@
+pub mod cache;
+pub mod comment;
+pub mod common;
+pub mod connection;
+pub mod discovery;
+pub mod duplicates;
+pub mod fingerprint;
+pub mod keyring;
+pub mod limits;
+pub mod note;
+pub mod policy;
+pub mod profiles;
+pub mod refresh;
+pub mod server;
+pub mod shutdown;
+pub mod upstream;
@@ -0,0 +1,110 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY};
+    use crate::config::KeyOptions;
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{PublicCredential, SignRequest};
+    use ssh_key::PrivateKey;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn agent_with_note(note: &str) -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default()
+            .with_secret(secret_id(1), "ed25519", ED25519_KEY)
+            .with_note(secret_id(1), note);
+        BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)])
+    }
+
+    fn sign_request(data: &[u8]) -> SignRequest {
+        let key_data = PrivateKey::from_openssh(ED25519_KEY)
+            .unwrap()
+            .public_key()
+            .key_data()
+            .clone();
+        SignRequest {
+            credential: PublicCredential::Key(key_data),
+            data: data.to_vec(),
+            flags: 0,
+        }
+    }
+
+    /// Start of the blob `ssh-keygen -Y sign -n <namespace>` asks the agent to sign
+    fn sshsig_blob(namespace: &str) -> Vec<u8> {
+        let mut blob = b"SSHSIG".to_vec();
+        blob.extend_from_slice(&(namespace.len() as u32).to_be_bytes());
+        blob.extend_from_slice(namespace.as_bytes());
+        blob.extend_from_slice(&[0, 0, 0, 0]);
+        blob
+    }
+
+    #[tokio::test]
+    async fn note_without_option_block_is_ignored() {
+        let mut agent = agent_with_note("Deploy key, rotate yearly");
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities[0].comment, "ed25519");
+    }
+
+    #[tokio::test]
+    async fn comment_is_read_from_the_note() {
+        let mut agent = agent_with_note(
+            "Deploy key\n\n[vault-conductor]\ncomment: deploy@prod\n\n[other-tool]\nfoo: bar\n",
+        );
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities[0].comment, "deploy@prod");
+    }
+
+    #[tokio::test]
+    async fn local_options_take_precedence_over_the_note() {
+        let options = HashMap::from([(
+            secret_id(1),
+            KeyOptions {
+                comment: Some("local".to_string()),
+                ..Default::default()
+            },
+        )]);
+        let mut agent = agent_with_note("[vault-conductor]\ncomment: from-note\nhidden: false")
+            .with_key_options(&options);
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities[0].comment, "local");
+    }
+
+    #[tokio::test]
+    async fn hidden_key_is_not_advertised_but_signs() {
+        let mut agent = agent_with_note("[vault-conductor]\nhidden: true");
+
+        assert!(agent.request_identities().await.unwrap().is_empty());
+        agent.sign(sign_request(b"data to sign")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn namespaces_restrict_what_the_key_signs() {
+        let mut agent = agent_with_note("[vault-conductor]\nnamespaces: [git]");
+
+        agent.sign(sign_request(&sshsig_blob("git"))).await.unwrap();
+        assert!(agent
+            .sign(sign_request(&sshsig_blob("file")))
+            .await
+            .is_err());
+        assert!(agent.sign(sign_request(b"ssh session data")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_option_block_refuses_the_key() {
+        let agent = agent_with_note("[vault-conductor]\nconfrim: true");
+
+        let checks = agent.check_keys().await;
+        let error = checks[0]
+            .result
+            .as_ref()
+            .expect_err("unknown option should fail");
+        assert!(error.contains("Invalid [vault-conductor] block"));
+    }
+}
@@ -0,0 +1,144 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::policy::{SSH_AGENT_RSA_SHA2_256, SSH_AGENT_RSA_SHA2_512};
+    use crate::bitwarden::tests::common::{
+        bundle, secret_id, MockFetcher, ED25519_KEY, RSA_1024_KEY, RSA_2048_KEY,
+    };
+    use crate::config::KeyPolicy;
+
+    use signature::Verifier;
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{PublicCredential, SignRequest};
+    use ssh_key::{Algorithm, HashAlg};
+    use std::sync::Arc;
+
+    fn agent(policy: KeyPolicy) -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default()
+            .with_secret(secret_id(1), "ed25519", ED25519_KEY)
+            .with_secret(secret_id(2), "rsa-2048", RSA_2048_KEY)
+            .with_secret(secret_id(3), "rsa-1024", RSA_1024_KEY);
+        BitwardenAgent::new(
+            Arc::new(fetcher),
+            vec![secret_id(1), secret_id(2), secret_id(3)],
+        )
+        .with_key_policy(policy)
+    }
+
+    async fn advertised_comments(agent: &mut BitwardenAgent<MockFetcher>) -> Vec<String> {
+        agent
+            .request_identities()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|identity| identity.comment)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn weak_rsa_keys_are_skipped() {
+        let mut agent = agent(KeyPolicy::default());
+
+        assert_eq!(
+            advertised_comments(&mut agent).await,
+            ["ed25519", "rsa-2048"]
+        );
+
+        let checks = agent.check_keys().await;
+        let error = checks[2].result.as_ref().expect_err("weak key should fail");
+        assert!(error.contains("1024 bits is below the minimum of 2048 bits"));
+    }
+
+    #[tokio::test]
+    async fn weak_key_of_a_bundle_is_skipped() {
+        let value = bundle(&[RSA_1024_KEY, ED25519_KEY]);
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "keyring", &value);
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)]);
+
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert!(matches!(
+            &identities[0].credential,
+            PublicCredential::Key(key) if key.algorithm() == Algorithm::Ed25519
+        ));
+        let checks = agent.check_keys().await;
+        assert!(checks[0].result.is_ok());
+        assert_eq!(checks[0].rejected.len(), 1);
+        assert!(checks[0].rejected[0]
+            .reason
+            .contains("1024 bits is below the minimum of 2048 bits"));
+    }
+
+    #[tokio::test]
+    async fn disallowed_algorithms_are_skipped() {
+        let mut agent = agent(KeyPolicy {
+            allowed_algorithms: Some(vec!["ssh-ed25519".to_string()]),
+            ..Default::default()
+        });
+
+        assert_eq!(advertised_comments(&mut agent).await, ["ed25519"]);
+    }
+
+    #[tokio::test]
+    async fn rsa_signatures_honor_requested_hash() {
+        let mut agent = agent(KeyPolicy::default());
+        let identity = agent.request_identities().await.unwrap().remove(1);
+        let PublicCredential::Key(key_data) = identity.credential.clone() else {
+            panic!("expected a plain public key");
+        };
+
+        for (flags, hash) in [
+            (SSH_AGENT_RSA_SHA2_256, HashAlg::Sha256),
+            (SSH_AGENT_RSA_SHA2_512, HashAlg::Sha512),
+        ] {
+            let signature = agent
+                .sign(SignRequest {
+                    credential: identity.credential.clone(),
+                    data: b"data to sign".to_vec(),
+                    flags,
+                })
+                .await
+                .unwrap();
+
+            assert_eq!(signature.algorithm(), Algorithm::Rsa { hash: Some(hash) });
+            key_data
+                .verify(b"data to sign", &signature)
+                .expect("signature should verify with the advertised key");
+        }
+    }
+
+    #[tokio::test]
+    async fn sha1_signatures_are_refused_by_default() {
+        let mut agent = agent(KeyPolicy::default());
+        let identity = agent.request_identities().await.unwrap().remove(1);
+
+        let result = agent
+            .sign(SignRequest {
+                credential: identity.credential,
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn rsa_signature_algorithms_allow_rsa_keys_with_their_hash_only() {
+        let mut agent = agent(KeyPolicy {
+            allowed_algorithms: Some(vec!["rsa-sha2-512".to_string()]),
+            ..Default::default()
+        });
+        assert_eq!(advertised_comments(&mut agent).await, ["rsa-2048"]);
+        let identity = agent.request_identities().await.unwrap().remove(0);
+
+        let sign = |flags| SignRequest {
+            credential: identity.credential.clone(),
+            data: b"data to sign".to_vec(),
+            flags,
+        };
+        assert!(agent.sign(sign(SSH_AGENT_RSA_SHA2_512)).await.is_ok());
+        assert!(agent.sign(sign(SSH_AGENT_RSA_SHA2_256)).await.is_err());
+    }
+}
@@ -0,0 +1,109 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+    use crate::config::KeyOptions;
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::SignRequest;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn fetcher() -> MockFetcher {
+        MockFetcher::default()
+            .with_secret(secret_id(1), "personal", ED25519_KEY)
+            .with_secret(secret_id(2), "ci-deploy", RSA_2048_KEY)
+    }
+
+    fn agent(fetcher: &MockFetcher) -> BitwardenAgent<MockFetcher> {
+        BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1), secret_id(2)])
+    }
+
+    fn comments(identities: &[ssh_agent_lib::proto::Identity]) -> Vec<&str> {
+        identities
+            .iter()
+            .map(|identity| identity.comment.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn view_serves_only_its_secrets() {
+        let fetcher = fetcher();
+        let agent = agent(&fetcher);
+        let mut personal = agent.view(&[secret_id(1)]).unwrap();
+        let mut ci = agent.view(&[secret_id(2)]).unwrap();
+
+        let personal_identities = personal.request_identities().await.unwrap();
+        let ci_identities = ci.request_identities().await.unwrap();
+
+        assert_eq!(comments(&personal_identities), vec!["personal"]);
+        assert_eq!(comments(&ci_identities), vec!["ci-deploy"]);
+        let refused = personal
+            .sign(SignRequest {
+                credential: ci_identities[0].credential.clone(),
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await;
+        assert!(refused.is_err());
+    }
+
+    #[tokio::test]
+    async fn views_share_the_key_cache() {
+        let fetcher = fetcher();
+        let agent = agent(&fetcher);
+        let mut first = agent.view(&[secret_id(1)]).unwrap();
+        let mut second = agent.view(&[secret_id(1), secret_id(2)]).unwrap();
+
+        first.request_identities().await.unwrap();
+        second.request_identities().await.unwrap();
+
+        assert_eq!(fetcher.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn view_of_unknown_secret_is_refused() {
+        let agent = agent(&fetcher());
+
+        assert!(agent.view(&[secret_id(3)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn views_are_locked_separately() {
+        let fetcher = fetcher();
+        let agent = agent(&fetcher);
+        let mut personal = agent.view(&[secret_id(1)]).unwrap();
+        let mut ci = agent.view(&[secret_id(2)]).unwrap();
+
+        personal.lock("passphrase".to_string()).await.unwrap();
+
+        assert!(personal.request_identities().await.unwrap().is_empty());
+        assert_eq!(ci.request_identities().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn signature_limit_is_shared_between_views() {
+        let options = HashMap::from([(
+            secret_id(1),
+            KeyOptions {
+                max_signatures: Some(1),
+                ..Default::default()
+            },
+        )]);
+        let agent = agent(&fetcher()).with_key_options(&options);
+        let mut first = agent.view(&[secret_id(1)]).unwrap();
+        let mut second = agent.view(&[secret_id(1)]).unwrap();
+
+        let credential = first.request_identities().await.unwrap()[0]
+            .credential
+            .clone();
+        let request = SignRequest {
+            credential,
+            data: b"data to sign".to_vec(),
+            flags: 0,
+        };
+        first.sign(request.clone()).await.unwrap();
+
+        assert!(second.sign(request).await.is_err());
+    }
+}
@@ -0,0 +1,105 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::refresh::Refresher;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+
+    use ssh_agent_lib::agent::Session;
+    use ssh_key::PrivateKey;
+    use std::sync::Arc;
+
+    fn fetcher() -> MockFetcher {
+        MockFetcher::default()
+            .with_secret(secret_id(1), "deploy", ED25519_KEY)
+            .with_secret(secret_id(2), "backup", RSA_2048_KEY)
+    }
+
+    fn public_key(key: &str) -> ssh_key::public::KeyData {
+        PrivateKey::from_openssh(key)
+            .unwrap()
+            .public_key()
+            .key_data()
+            .clone()
+    }
+
+    async fn served_keys(agent: &mut BitwardenAgent<MockFetcher>) -> Vec<String> {
+        agent
+            .request_identities()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|identity| identity.comment)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rotated_key_replaces_the_cached_one() {
+        let fetcher = fetcher();
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1)]);
+        agent.request_identities().await.unwrap();
+
+        fetcher.rotate(secret_id(1), RSA_2048_KEY);
+        let rotated = agent.refresh().await;
+        let identities = agent.request_identities().await.unwrap();
+
+        assert_eq!(rotated, vec![secret_id(1)]);
+        assert_eq!(identities.len(), 1);
+        assert_eq!(
+            identities[0].credential,
+            ssh_agent_lib::proto::PublicCredential::Key(public_key(RSA_2048_KEY))
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_and_unloaded_keys_are_not_rotated() {
+        let fetcher = fetcher();
+        let mut agent =
+            BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1), secret_id(2)]);
+        agent
+            .view(&[secret_id(1)])
+            .unwrap()
+            .request_identities()
+            .await
+            .unwrap();
+        let fetches = fetcher.fetches();
+
+        let rotated = agent.refresh().await;
+
+        assert!(rotated.is_empty());
+        // Only the loaded secret is fetched again
+        assert_eq!(fetcher.fetches(), fetches + 1);
+        assert_eq!(served_keys(&mut agent).await, vec!["deploy", "backup"]);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_the_cached_key() {
+        let fetcher = fetcher();
+        let mut agent = BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1)]);
+        agent.request_identities().await.unwrap();
+
+        fetcher.make_unavailable(secret_id(1));
+        let rotated = agent.refresh().await;
+
+        assert!(rotated.is_empty());
+        assert_eq!(served_keys(&mut agent).await, vec!["deploy"]);
+    }
+
+    #[tokio::test]
+    async fn project_changes_are_served_on_the_default_view() {
+        let fetcher = fetcher().with_discovered(&[secret_id(2)]);
+        let agent = BitwardenAgent::new(Arc::new(fetcher.clone()), vec![secret_id(1)]);
+        let mut default_view = agent.view(&[secret_id(1)]).unwrap();
+        let refresher = Refresher::new(
+            Arc::new(fetcher),
+            agent.clone(),
+            default_view.clone(),
+            vec![],
+        )
+        .with_watched_projects(true);
+
+        refresher.refresh().await;
+
+        assert_eq!(default_view.served_secrets(), vec![secret_id(2)]);
+        assert_eq!(served_keys(&mut default_view).await, vec!["backup"]);
+    }
+}
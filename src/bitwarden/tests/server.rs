@@ -0,0 +1,67 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::server::serve_session;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY};
+
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// `SSH_AGENTC_REQUEST_IDENTITIES`, framed
+    const REQUEST_IDENTITIES: [u8; 5] = [0, 0, 0, 1, 11];
+
+    fn agent() -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "ed25519", ED25519_KEY);
+        BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)])
+    }
+
+    #[tokio::test]
+    async fn closed_input_ends_the_session() {
+        let mut agent = agent();
+
+        serve_session(&mut agent, tokio::io::empty(), tokio::io::sink())
+            .await
+            .expect("an empty input is a session without requests");
+    }
+
+    #[tokio::test]
+    async fn every_request_gets_a_framed_response() {
+        let mut agent = agent();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (reader, writer) = tokio::io::split(server);
+        let serving = tokio::spawn(async move { serve_session(&mut agent, reader, writer).await });
+
+        for _ in 0..2 {
+            client.write_all(&REQUEST_IDENTITIES).await.unwrap();
+            let length = client.read_u32().await.unwrap() as usize;
+            let mut response = vec![0u8; length];
+            client.read_exact(&mut response).await.unwrap();
+            assert!(!response.is_empty());
+        }
+        drop(client);
+
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_request_is_refused() {
+        let mut agent = agent();
+        let mut input: &[u8] = &[0x7f, 0xff, 0xff, 0xff];
+
+        let error = serve_session(&mut agent, &mut input, tokio::io::sink())
+            .await
+            .expect_err("a request over the size limit should fail");
+
+        assert!(error.to_string().contains("exceeds the limit"));
+    }
+
+    #[tokio::test]
+    async fn truncated_request_is_an_error() {
+        let mut agent = agent();
+        let mut input: &[u8] = &[0, 0, 0, 8, 11];
+
+        assert!(serve_session(&mut agent, &mut input, tokio::io::sink())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::shutdown::{RequestTracker, TrackedSession};
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY};
+
+    use ssh_agent_lib::agent::Session;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn drain_without_requests_returns_at_once() {
+        let requests = Arc::new(RequestTracker::default());
+
+        assert!(requests.drain(Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_requests_in_flight() {
+        let requests = Arc::new(RequestTracker::default());
+        let request = requests
+            .begin()
+            .expect("requests are accepted before draining");
+
+        let draining = tokio::spawn({
+            let requests = requests.clone();
+            async move { requests.drain(Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!draining.is_finished());
+        drop(request);
+
+        assert!(draining.await.unwrap());
+        assert_eq!(requests.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_the_timeout() {
+        let requests = Arc::new(RequestTracker::default());
+        let _request = requests.begin().unwrap();
+
+        assert!(!requests.drain(Duration::from_millis(50)).await);
+        assert_eq!(requests.in_flight(), 1);
+    }
+
+    #[tokio::test]
+    async fn requests_are_refused_while_draining() {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "ed25519", ED25519_KEY);
+        let agent = BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)]);
+        let requests = Arc::new(RequestTracker::default());
+        let mut session = TrackedSession::new(agent, requests.clone());
+
+        assert_eq!(session.request_identities().await.unwrap().len(), 1);
+        assert!(requests.drain(Duration::from_secs(1)).await);
+
+        assert!(session.request_identities().await.is_err());
+        assert_eq!(requests.in_flight(), 0);
+    }
+}
@@ -0,0 +1,106 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::bitwarden::agent::BitwardenAgent;
+    use crate::bitwarden::policy::SSH_AGENT_RSA_SHA2_256;
+    use crate::bitwarden::tests::common::{secret_id, MockFetcher, ED25519_KEY, RSA_2048_KEY};
+    use crate::bitwarden::upstream::{ProxyAgent, UpstreamConnector};
+
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use signature::Verifier;
+    use ssh_agent_lib::agent::Session;
+    use ssh_agent_lib::proto::{PublicCredential, SignRequest};
+    use std::sync::Arc;
+
+    /// Upstream agent served in-process by another agent instance
+    struct MockUpstream(Option<BitwardenAgent<MockFetcher>>);
+
+    #[async_trait]
+    impl UpstreamConnector for MockUpstream {
+        async fn connect(&self) -> Result<Box<dyn Session>> {
+            match &self.0 {
+                Some(agent) => Ok(Box::new(agent.clone())),
+                None => Err(anyhow!("Failed to connect to upstream agent")),
+            }
+        }
+    }
+
+    fn local_agent() -> BitwardenAgent<MockFetcher> {
+        let fetcher = MockFetcher::default().with_secret(secret_id(1), "bitwarden", ED25519_KEY);
+        BitwardenAgent::new(Arc::new(fetcher), vec![secret_id(1)])
+    }
+
+    fn upstream() -> MockUpstream {
+        let fetcher = MockFetcher::default().with_secret(secret_id(2), "hardware", RSA_2048_KEY);
+        MockUpstream(Some(BitwardenAgent::new(
+            Arc::new(fetcher),
+            vec![secret_id(2)],
+        )))
+    }
+
+    #[tokio::test]
+    async fn identities_are_merged() {
+        let mut proxy = ProxyAgent::new(local_agent(), upstream());
+
+        let identities = proxy.request_identities().await.unwrap();
+
+        let comments: Vec<_> = identities.iter().map(|i| i.comment.as_str()).collect();
+        assert_eq!(comments, ["bitwarden", "hardware"]);
+    }
+
+    #[tokio::test]
+    async fn upstream_keys_sign_through_the_upstream_agent() {
+        let mut proxy = ProxyAgent::new(local_agent(), upstream());
+        let identity = proxy.request_identities().await.unwrap().remove(1);
+
+        let signature = proxy
+            .sign(SignRequest {
+                credential: identity.credential.clone(),
+                data: b"data to sign".to_vec(),
+                flags: SSH_AGENT_RSA_SHA2_256,
+            })
+            .await
+            .unwrap();
+
+        let PublicCredential::Key(key_data) = identity.credential else {
+            panic!("expected a plain public key");
+        };
+        key_data.verify(b"data to sign", &signature).unwrap();
+    }
+
+    #[tokio::test]
+    async fn key_policy_applies_to_upstream_keys() {
+        let mut proxy = ProxyAgent::new(local_agent(), upstream());
+        let identity = proxy.request_identities().await.unwrap().remove(1);
+
+        // SHA-1 signatures are refused by the default policy
+        let result = proxy
+            .sign(SignRequest {
+                credential: identity.credential,
+                data: b"data to sign".to_vec(),
+                flags: 0,
+            })
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_serves_bitwarden_keys_only() {
+        let mut proxy = ProxyAgent::new(local_agent(), MockUpstream(None));
+
+        let identities = proxy.request_identities().await.unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].comment, "bitwarden");
+    }
+
+    #[tokio::test]
+    async fn locked_proxy_hides_upstream_keys() {
+        let mut proxy = ProxyAgent::new(local_agent(), upstream());
+
+        proxy.lock("passphrase".to_string()).await.unwrap();
+
+        assert!(proxy.request_identities().await.unwrap().is_empty());
+    }
+}
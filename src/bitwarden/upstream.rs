@@ -0,0 +1,220 @@
+use crate::bitwarden::agent::{BitwardenAgent, SecretFetcher};
+use crate::bitwarden::connection::ConnectionContext;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use ssh_agent_lib::agent::Session;
+use ssh_agent_lib::client::Client;
+use ssh_agent_lib::error::AgentError;
+use ssh_agent_lib::proto::{Extension, Identity, PublicCredential, SignRequest};
+use ssh_key::public::KeyData;
+use ssh_key::{HashAlg, PublicKey, Signature};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::UnixStream;
+
+/// Opens sessions with the agent whose keys are served next to the Bitwarden ones
+#[async_trait]
+pub trait UpstreamConnector: Send + Sync + 'static {
+    async fn connect(&self) -> Result<Box<dyn Session>>;
+}
+
+/// Upstream agent listening on a Unix socket (e.g. the original `SSH_AUTH_SOCK`)
+pub struct UnixSocketUpstream {
+    socket_path: PathBuf,
+}
+
+impl UnixSocketUpstream {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Self { socket_path }
+    }
+}
+
+#[async_trait]
+impl UpstreamConnector for UnixSocketUpstream {
+    async fn connect(&self) -> Result<Box<dyn Session>> {
+        let stream = UnixStream::connect(&self.socket_path)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to connect to upstream agent at {}",
+                    self.socket_path.display()
+                )
+            })?;
+        Ok(Box::new(Client::new(stream)))
+    }
+}
+
+/// Agent presenting the Bitwarden keys merged with the keys of an upstream agent.
+///
+/// Bitwarden keys take precedence, sign requests for any other key are forwarded
+/// to the upstream agent after the key policy has been applied to them.
+pub struct ProxyAgent<F: SecretFetcher + Clone, U: UpstreamConnector> {
+    local: BitwardenAgent<F>,
+    upstream: Arc<U>,
+}
+
+// Not derived, it would require `U: Clone`
+impl<F: SecretFetcher + Clone, U: UpstreamConnector> Clone for ProxyAgent<F, U> {
+    fn clone(&self) -> Self {
+        Self {
+            local: self.local.clone(),
+            upstream: self.upstream.clone(),
+        }
+    }
+}
+
+impl<F: SecretFetcher + Clone, U: UpstreamConnector> ProxyAgent<F, U> {
+    pub fn new(local: BitwardenAgent<F>, upstream: U) -> Self {
+        Self {
+            local,
+            upstream: Arc::new(upstream),
+        }
+    }
+
+    /// Tag the log lines and confirmation prompts of this session with its client
+    pub fn with_connection(mut self, connection: ConnectionContext) -> Self {
+        self.local = self.local.with_connection(connection);
+        self
+    }
+
+    /// Identities of the upstream agent complying with the key policy
+    async fn upstream_identities(&self) -> Result<Vec<Identity>> {
+        let mut session = self.upstream.connect().await?;
+        let identities = session
+            .request_identities()
+            .await
+            .context("Upstream agent failed to list identities")?;
+
+        Ok(identities
+            .into_iter()
+            .filter(|identity| {
+                let key = PublicKey::from(credential_key(&identity.credential).clone());
+                match self.local.key_policy().check_key(&key) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(
+                            "[{}] Upstream key '{}' rejected by key policy: {}",
+                            self.local.connection(),
+                            identity.comment,
+                            e
+                        );
+                        false
+                    }
+                }
+            })
+            .collect())
+    }
+}
+
+/// Key a credential signs with, certificates sign with the key they certify
+fn credential_key(credential: &PublicCredential) -> &KeyData {
+    match credential {
+        PublicCredential::Key(key_data) => key_data,
+        PublicCredential::Cert(certificate) => certificate.public_key(),
+    }
+}
+
+fn upstream_error(message: String) -> AgentError {
+    AgentError::other(Box::new(std::io::Error::other(message)))
+}
+
+#[async_trait]
+impl<F: SecretFetcher + Clone + 'static, U: UpstreamConnector> Session for ProxyAgent<F, U> {
+    async fn request_identities(&mut self) -> Result<Vec<Identity>, AgentError> {
+        let mut identities = self.local.request_identities().await?;
+        // A locked agent advertises no keys, upstream ones included
+        if self.local.is_locked() {
+            return Ok(identities);
+        }
+
+        match self.upstream_identities().await {
+            Ok(upstream) => {
+                let local_credentials: Vec<PublicCredential> = identities
+                    .iter()
+                    .map(|identity| identity.credential.clone())
+                    .collect();
+                let mut added = 0;
+                for identity in upstream {
+                    if local_credentials.contains(&identity.credential) {
+                        debug!(
+                            "[{}] Upstream key '{}' is also stored in Bitwarden, skipping it",
+                            self.local.connection(),
+                            identity.comment
+                        );
+                        continue;
+                    }
+                    identities.push(identity);
+                    added += 1;
+                }
+                debug!(
+                    "[{}] Merged {} identities from the upstream agent",
+                    self.local.connection(),
+                    added
+                );
+            }
+            Err(e) => warn!(
+                "[{}] {:#}. Serving Bitwarden keys only.",
+                self.local.connection(),
+                e
+            ),
+        }
+
+        Ok(identities)
+    }
+
+    async fn sign(&mut self, request: SignRequest) -> Result<Signature, AgentError> {
+        let key = credential_key(&request.credential).clone();
+        if self.local.is_locked() || self.local.holds_key(&key).await {
+            return self.local.sign(request).await;
+        }
+
+        let public_key = PublicKey::from(key);
+        let fingerprint = public_key.fingerprint(HashAlg::Sha256);
+        let policy = self.local.key_policy();
+        policy
+            .check_key(&public_key)
+            .and_then(|_| policy.signature_hash(public_key.algorithm(), request.flags))
+            .map_err(|e| {
+                warn!(
+                    "[{}] Sign request for upstream key {} refused by key policy: {}",
+                    self.local.connection(),
+                    fingerprint,
+                    e
+                );
+                upstream_error(e.to_string())
+            })?;
+
+        info!(
+            "[{}] Forwarding sign request for key {} to the upstream agent",
+            self.local.connection(),
+            fingerprint
+        );
+        let mut session = self
+            .upstream
+            .connect()
+            .await
+            .map_err(|e| upstream_error(format!("{:#}", e)))?;
+        session.sign(request).await.map_err(|e| {
+            warn!(
+                "[{}] Upstream agent failed to sign with key {}: {}",
+                self.local.connection(),
+                fingerprint,
+                e
+            );
+            e
+        })
+    }
+
+    async fn lock(&mut self, key: String) -> Result<(), AgentError> {
+        self.local.lock(key).await
+    }
+
+    async fn unlock(&mut self, key: String) -> Result<(), AgentError> {
+        self.local.unlock(key).await
+    }
+
+    async fn extension(&mut self, extension: Extension) -> Result<Option<Extension>, AgentError> {
+        self.local.extension(extension).await
+    }
+}
@@ -1,15 +1,198 @@
+use crate::backends::age::AgeConfig;
+use crate::backends::bw_serve::BwServeConfig;
+use crate::backends::keepass::KeePassConfig;
+use crate::backends::plugin::PluginConfig;
+use crate::backends::vault::VaultConfig;
+use crate::backends::BackendKind;
+use crate::bitwarden::comment::validate_template;
+use crate::file_manager::get_socket_file_path;
 use anyhow::{bail, Context, Result};
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 pub const CONFIG_FILE: &str = ".config/vault-conductor/config.yaml";
 
-#[derive(Debug, Deserialize)]
+/// Seconds requests in flight are given to finish when the agent stops
+pub const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+
+#[derive(Clone, Deserialize)]
 pub struct Config {
-    pub bws_access_token: String,
+    /// Where the keys are stored, `bitwarden` by default
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// Secrets holding the keys, in the format of the backend (e.g. UUIDs, names or
+    /// name patterns for Bitwarden)
+    #[serde(alias = "secret_ids", default)]
     pub bw_secret_ids: Vec<String>,
+    /// Bitwarden projects whose secrets holding SSH keys are served too
+    #[serde(default)]
+    pub bw_project_ids: Vec<String>,
+    /// Bitwarden organization whose secrets are listed to resolve secret names
+    #[serde(default)]
+    pub bw_organization_id: Option<String>,
+    /// Bitwarden machine account access token
+    #[serde(default)]
+    pub bws_access_token: Zeroizing<String>,
+    /// Save the Bitwarden authentication state across restarts instead of logging in
+    /// on every start
+    #[serde(default)]
+    pub bw_persist_auth_state: bool,
     #[serde(default)]
     pub bw_server_endpoint: Option<String>,
+    /// Settings of the `bw-serve` backend
+    #[serde(default)]
+    pub bw_serve: BwServeConfig,
+    /// Settings of the `vault` backend
+    #[serde(default)]
+    pub vault: VaultConfig,
+    /// Settings of the `keepass` backend
+    #[serde(default)]
+    pub keepass: KeePassConfig,
+    /// Settings of the `age` backend
+    #[serde(default)]
+    pub age: AgeConfig,
+    /// Settings of the `plugin` backend
+    #[serde(default)]
+    pub plugin: PluginConfig,
+    /// Per-key options, keyed by secret ID
+    #[serde(default)]
+    pub key_options: HashMap<String, KeyOptions>,
+    /// Key strength and algorithm requirements every key must meet
+    #[serde(default)]
+    pub key_policy: KeyPolicy,
+    /// Template of the comment advertised for each key, e.g. `{name} ({project})`
+    #[serde(default)]
+    pub comment_template: Option<String>,
+    /// Socket of another agent whose keys are served next to the Bitwarden ones
+    #[serde(default)]
+    pub upstream_agent: Option<String>,
+    /// Extra agent sockets, each serving a subset of the keys, keyed by profile name
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Seconds requests in flight are given to finish when the agent stops
+    #[serde(default)]
+    pub shutdown_timeout: Option<u64>,
+    /// Seconds between two refreshes of the keys from the backend, never by default
+    #[serde(default)]
+    pub refresh_interval: Option<u64>,
+}
+
+/// Written by hand to keep the access token out of logs and error messages
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("backend", &self.backend)
+            .field("bw_secret_ids", &self.bw_secret_ids)
+            .field("bw_project_ids", &self.bw_project_ids)
+            .field("bw_organization_id", &self.bw_organization_id)
+            .field("bws_access_token", &"[REDACTED]")
+            .field("bw_persist_auth_state", &self.bw_persist_auth_state)
+            .field("bw_server_endpoint", &self.bw_server_endpoint)
+            .field("bw_serve", &self.bw_serve)
+            .field("vault", &self.vault)
+            .field("keepass", &self.keepass)
+            .field("age", &self.age)
+            .field("plugin", &self.plugin)
+            .field("key_options", &self.key_options)
+            .field("key_policy", &self.key_policy)
+            .field("comment_template", &self.comment_template)
+            .field("upstream_agent", &self.upstream_agent)
+            .field("profiles", &self.profiles)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("refresh_interval", &self.refresh_interval)
+            .finish()
+    }
+}
+
+/// Agent socket serving only some of the keys, e.g. to mount into a container
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Secrets served on the socket of the profile
+    pub secret_ids: Vec<String>,
+    /// Path of the socket, defaults to `/tmp/vc-$USER-ssh-agent-<profile>.sock`
+    #[serde(default)]
+    pub socket: Option<String>,
+}
+
+impl Profile {
+    /// Socket of the profile named `name`
+    pub fn socket_path(&self, name: &str) -> PathBuf {
+        match &self.socket {
+            Some(socket) => PathBuf::from(socket),
+            None => get_socket_file_path(Some(name)),
+        }
+    }
+}
+
+/// Key strength and algorithm policy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyPolicy {
+    /// Minimum RSA modulus size in bits
+    pub min_rsa_bits: u32,
+    /// Key algorithms allowed to be loaded (e.g. `ssh-ed25519`, `ssh-rsa`), all if unset.
+    /// `rsa-sha2-256` and `rsa-sha2-512` allow RSA keys but only signatures with that hash.
+    pub allowed_algorithms: Option<Vec<String>>,
+    /// Whether RSA signatures using SHA-1 (`ssh-rsa`) may be produced
+    pub allow_sha1: bool,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            min_rsa_bits: 2048,
+            allowed_algorithms: None,
+            allow_sha1: false,
+        }
+    }
+}
+
+/// Options applied to a single key
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct KeyOptions {
+    /// Maximum number of signatures the key may produce before being hidden
+    #[serde(default)]
+    pub max_signatures: Option<u64>,
+    /// Seconds after the first signature when the signature counter resets.
+    /// Without it, `max_signatures` applies until `vault-conductor reset-counters` or a restart.
+    #[serde(default)]
+    pub max_signatures_window: Option<u64>,
+    /// Expected fingerprint of the key (e.g. `SHA256:...`), the key is refused if it differs
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Comment advertised for the key instead of the secret name
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Ask for confirmation through `SSH_ASKPASS` before every signature
+    #[serde(default)]
+    pub confirm: Option<bool>,
+    /// Seconds the key stays in memory before it is wiped and fetched again
+    #[serde(default)]
+    pub lifetime: Option<u64>,
+    /// `ssh-keygen -Y sign` namespaces the key may sign for, anything else is refused
+    #[serde(default)]
+    pub namespaces: Option<Vec<String>>,
+    /// Do not advertise the key, it still signs for clients that ask for it explicitly
+    #[serde(default)]
+    pub hidden: Option<bool>,
+    /// Sources tried in order when the backend fails to return the key, e.g. when
+    /// offline. Requires `fingerprint`, every source must return the same key.
+    #[serde(default)]
+    pub fallbacks: Vec<KeySource>,
+}
+
+/// Secret holding a copy of a key in another backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeySource {
+    pub backend: BackendKind,
+    /// Reference of the secret, in the format of that backend
+    pub id: String,
 }
 
 impl Config {
@@ -20,13 +203,36 @@ impl Config {
         };
 
         let mut config: Config = Config {
-            bws_access_token: std::env::var("BWS_ACCESS_TOKEN").unwrap_or_default(),
+            backend: BackendKind::default(),
+            bws_access_token: Zeroizing::new(std::env::var("BWS_ACCESS_TOKEN").unwrap_or_default()),
+            bw_persist_auth_state: std::env::var("BW_PERSIST_AUTH_STATE")
+                .is_ok_and(|value| matches!(value.as_str(), "1" | "true")),
             bw_secret_ids: std::env::var("BW_SECRET_IDS")
                 .unwrap_or_default()
                 .split(',')
                 .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
                 .collect(),
+            bw_project_ids: std::env::var("BW_PROJECT_IDS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            bw_organization_id: std::env::var("BW_ORGANIZATION_ID").ok(),
             bw_server_endpoint: std::env::var("BW_SERVER_ENDPOINT").ok().or(None),
+            bw_serve: BwServeConfig::default(),
+            vault: VaultConfig::default(),
+            keepass: KeePassConfig::default(),
+            age: AgeConfig::default(),
+            plugin: PluginConfig::default(),
+            key_options: HashMap::new(),
+            key_policy: KeyPolicy::default(),
+            comment_template: None,
+            upstream_agent: None,
+            profiles: HashMap::new(),
+            shutdown_timeout: None,
+            refresh_interval: None,
         };
 
         // Try to load from config file first
@@ -58,58 +264,164 @@ impl Config {
         Ok(config)
     }
 
+    /// Every secret reference of the configuration: served secrets, profile secrets and
+    /// the keys of `key_options`
+    pub fn secret_references(&self) -> impl Iterator<Item = &String> {
+        let profile_ids = self
+            .profiles
+            .values()
+            .flat_map(|profile| profile.secret_ids.iter());
+        self.bw_secret_ids
+            .iter()
+            .chain(profile_ids)
+            .chain(self.key_options.keys())
+    }
+
     fn validate(&self) -> Result<()> {
-        if self.bws_access_token.is_empty() {
-            bail!(format!(
-                "Config file not found at {} and BWS_ACCESS_TOKEN environment variable is not set",
-                Config::get_config_path()?.display()
-            ));
-        }
-        if self.bw_secret_ids.is_empty()
-            || self.bw_secret_ids.is_empty()
-            || self.bw_secret_ids.first().unwrap().trim().is_empty()
+        if (self.bw_secret_ids.is_empty() || self.bw_secret_ids.first().unwrap().trim().is_empty())
+            && self.bw_project_ids.is_empty()
         {
             bail!(format!(
                 "Config file not found at {} and BW_SECRET_IDS environment variable is not set",
                 Config::get_config_path()?.display()
             ));
         }
+        if !self.bw_project_ids.is_empty() && self.backend != BackendKind::Bitwarden {
+            bail!(
+                "bw_project_ids is only supported by the bitwarden backend, not {}",
+                self.backend.name()
+            );
+        }
+        if let Some(organization_id) = &self.bw_organization_id {
+            uuid::Uuid::parse_str(organization_id).with_context(|| {
+                format!(
+                    "Invalid Bitwarden organization ID, expected a UUID: {}",
+                    organization_id
+                )
+            })?;
+        }
+        if self.refresh_interval == Some(0) {
+            bail!("refresh_interval must be greater than 0");
+        }
+        for project_id in &self.bw_project_ids {
+            uuid::Uuid::parse_str(project_id).with_context(|| {
+                format!(
+                    "Invalid Bitwarden project ID, expected a UUID: {}",
+                    project_id
+                )
+            })?;
+        }
+        for (secret_id, options) in &self.key_options {
+            if options.max_signatures == Some(0) {
+                bail!(
+                    "Invalid options for key {}: max_signatures must be greater than 0",
+                    secret_id
+                );
+            }
+            if options.max_signatures_window.is_some() && options.max_signatures.is_none() {
+                bail!(
+                    "Invalid options for key {}: max_signatures_window requires max_signatures",
+                    secret_id
+                );
+            }
+            if let Some(fingerprint) = &options.fingerprint {
+                fingerprint
+                    .parse::<ssh_key::Fingerprint>()
+                    .with_context(|| {
+                        format!(
+                            "Invalid options for key {}: fingerprint '{}' is not a valid \
+                        SHA256:... or SHA512:... fingerprint",
+                            secret_id, fingerprint
+                        )
+                    })?;
+            }
+            if !options.fallbacks.is_empty() && options.fingerprint.is_none() {
+                bail!(
+                    "Invalid options for key {}: fallbacks require fingerprint, \
+                    to check that every source returns the same key",
+                    secret_id
+                );
+            }
+            for source in &options.fallbacks {
+                source.backend.validate_settings(self).with_context(|| {
+                    format!(
+                        "Invalid fallback of key {}: {} backend",
+                        secret_id,
+                        source.backend.name()
+                    )
+                })?;
+                source.backend.check_reference(self, &source.id)?;
+                // Fallbacks are not resolved, names would fail on every fetch
+                if source.backend.resolves(&source.id) {
+                    bail!(
+                        "Invalid fallback of key {}: '{}' must be the UUID of the {} secret, \
+                        names are not resolved for fallbacks",
+                        secret_id,
+                        source.id,
+                        source.backend.name()
+                    );
+                }
+            }
+            if options.namespaces.as_ref().is_some_and(Vec::is_empty) {
+                bail!(
+                    "Invalid options for key {}: namespaces must list at least one namespace",
+                    secret_id
+                );
+            }
+        }
+        for (name, profile) in &self.profiles {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "Invalid profile name '{}': use letters, digits, '-' and '_' only",
+                    name
+                );
+            }
+            if profile.secret_ids.is_empty() {
+                bail!(
+                    "Invalid profile {}: secret_ids must list at least one secret",
+                    name
+                );
+            }
+        }
+        if let Some(template) = &self.comment_template {
+            validate_template(template)?;
+        }
+        for algorithm in self.key_policy.allowed_algorithms.iter().flatten() {
+            ssh_key::Algorithm::new(algorithm).with_context(|| {
+                format!(
+                    "Unknown algorithm in key_policy.allowed_algorithms: {}",
+                    algorithm
+                )
+            })?;
+        }
+        // Settings owned by the backend, e.g. its credentials
+        self.backend.validate(self)?;
         Ok(())
     }
 
-    fn get_config_path() -> Result<PathBuf> {
+    pub(crate) fn get_config_path() -> Result<PathBuf> {
         let home_dir = dirs::home_dir().context("Unable to determine home directory")?;
         Ok(home_dir.join(CONFIG_FILE))
     }
 
-    pub fn get_api_url(&self) -> String {
-        self.bw_server_endpoint
-            .as_ref()
-            .map(|host| {
-                if host == "bitwarden.com" || host == "bitwarden.eu" {
-                    // Cloud instance - use subdomain pattern
-                    format!("https://api.{}", host)
-                } else {
-                    // Self-hosted - use path pattern
-                    format!("https://{}/api", host)
-                }
-            })
-            .unwrap_or_else(|| "https://api.bitwarden.com".to_string())
+    /// Sockets of every profile
+    pub fn profile_socket_paths(&self) -> Vec<PathBuf> {
+        self.profiles
+            .iter()
+            .map(|(name, profile)| profile.socket_path(name))
+            .collect()
     }
 
-    pub fn get_identity_url(&self) -> String {
-        self.bw_server_endpoint
-            .as_ref()
-            .map(|host| {
-                if host == "bitwarden.com" || host == "bitwarden.eu" {
-                    // Cloud instance - use subdomain pattern
-                    format!("https://identity.{}", host)
-                } else {
-                    // Self-hosted - use path pattern
-                    format!("https://{}/identity", host)
-                }
-            })
-            .unwrap_or_else(|| "https://identity.bitwarden.com".to_string())
+    pub fn get_shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT))
+    }
+
+    pub fn get_refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval.map(Duration::from_secs)
     }
 }
 
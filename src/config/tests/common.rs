@@ -15,11 +15,15 @@ pub fn test_path(name: &str) -> PathBuf {
 }
 
 pub fn create_config(path: &PathBuf, mode: u32) {
-    fs::write(
+    create_config_with_content(
         path,
+        mode,
         "bws_access_token: token\nbw_secret_ids:\n  - secret-id\n",
-    )
-    .expect("failed to write test config");
+    );
+}
+
+pub fn create_config_with_content(path: &PathBuf, mode: u32, content: &str) {
+    fs::write(path, content).expect("failed to write test config");
     fs::set_permissions(path, fs::Permissions::from_mode(mode))
         .expect("failed to set test config permissions");
 }
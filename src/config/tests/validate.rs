@@ -1,6 +1,7 @@
 #[cfg(all(test, unix))]
 mod tests {
-    use crate::config::tests::common::{create_config, test_path};
+    use crate::backends::BackendKind;
+    use crate::config::tests::common::{create_config, create_config_with_content, test_path};
     use crate::config::Config;
 
     use std::fs;
@@ -55,7 +56,247 @@ mod tests {
         let config = Config::load(&Some(path.to_string_lossy().into_owned()))
             .expect("environment-only configuration should load");
 
-        assert_eq!(config.bws_access_token, "environment-token");
+        assert_eq!(*config.bws_access_token, "environment-token");
         assert_eq!(config.bw_secret_ids, vec!["environment-secret-id"]);
     }
+
+    #[test]
+    fn access_token_is_redacted_from_debug_output() {
+        let path = test_path("redacted");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: secret-access-token\nbw_secret_ids:\n  - secret-id\n",
+        );
+
+        let config = Config::load(&Some(path.to_string_lossy().into_owned())).unwrap();
+
+        assert!(!format!("{config:?}").contains("secret-access-token"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn key_options_are_loaded() {
+        let path = test_path("key-options");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_secret_ids:\n  - secret-id\n\
+            key_options:\n  secret-id:\n    max_signatures: 1\n",
+        );
+
+        let config = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect("key options should load");
+
+        assert_eq!(config.key_options["secret-id"].max_signatures, Some(1));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn zero_signature_limit_is_rejected() {
+        let path = test_path("zero-signatures");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_secret_ids:\n  - secret-id\n\
+            key_options:\n  secret-id:\n    max_signatures: 0\n",
+        );
+
+        let error = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a zero signature limit should fail");
+
+        assert!(error.to_string().contains("max_signatures"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn profiles_are_loaded() {
+        let path = test_path("profiles");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_secret_ids:\n  - 00000000-0000-0000-0000-000000000001\n\
+            profiles:\n  ci-deploy: {socket: /run/ci/agent.sock, \
+            secret_ids: [00000000-0000-0000-0000-000000000002]}\n",
+        );
+
+        let config =
+            Config::load(&Some(path.to_string_lossy().into_owned())).expect("profiles should load");
+
+        let profile = &config.profiles["ci-deploy"];
+        assert_eq!(
+            profile.secret_ids,
+            vec!["00000000-0000-0000-0000-000000000002"]
+        );
+        assert_eq!(profile.socket.as_deref(), Some("/run/ci/agent.sock"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn profile_name_with_path_separator_is_rejected() {
+        let path = test_path("profile-name");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_secret_ids:\n  - 00000000-0000-0000-0000-000000000001\n\
+            profiles:\n  ../ci:\n    secret_ids:\n      - 00000000-0000-0000-0000-000000000001\n",
+        );
+
+        let error = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a profile name with a path separator should fail");
+
+        assert!(error.to_string().contains("Invalid profile name"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn backend_defaults_to_bitwarden() {
+        let path = test_path("backend-default");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nsecret_ids:\n  - 00000000-0000-0000-0000-000000000001\n",
+        );
+
+        let config =
+            Config::load(&Some(path.to_string_lossy().into_owned())).expect("config should load");
+
+        assert_eq!(config.backend, BackendKind::Bitwarden);
+        assert_eq!(
+            config.bw_secret_ids,
+            vec!["00000000-0000-0000-0000-000000000001"]
+        );
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn unknown_backend_is_rejected() {
+        let path = test_path("backend-unknown");
+        create_config_with_content(
+            &path,
+            0o600,
+            "backend: lastpass\nbws_access_token: token\nbw_secret_ids:\n  - secret-id\n",
+        );
+
+        let error = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("an unknown backend should fail");
+
+        assert!(format!("{:#}", error).contains("unknown variant `lastpass`"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn fallbacks_require_a_fingerprint() {
+        let path = test_path("fallback-fingerprint");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_secret_ids:\n  - secret-id\nage: {path: /keys}\n\
+            key_options:\n  secret-id:\n    fallbacks: [{backend: age, id: deploy}]\n",
+        );
+
+        let error = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("fallbacks without a fingerprint should fail");
+
+        assert!(error.to_string().contains("fallbacks require fingerprint"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn fallbacks_are_checked_against_their_backend() {
+        let path = test_path("fallback-backend");
+        let key_options = "key_options:\n  secret-id:\n    \
+            fingerprint: SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU\n    \
+            fallbacks: [{backend: age, id: ../deploy}]\n";
+        create_config_with_content(
+            &path,
+            0o600,
+            &format!("bws_access_token: token\nbw_secret_ids:\n  - secret-id\n{key_options}"),
+        );
+        let unset = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a fallback backend without settings should fail");
+        create_config_with_content(
+            &path,
+            0o600,
+            &format!(
+                "bws_access_token: token\nbw_secret_ids:\n  - secret-id\n\
+                age: {{path: /keys}}\n{key_options}"
+            ),
+        );
+        let invalid = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a fallback reference in another format should fail");
+
+        assert!(format!("{:#}", unset).contains("age.path must be set"));
+        assert!(invalid
+            .to_string()
+            .contains("Invalid age key name '../deploy'"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn bitwarden_fallbacks_require_a_uuid() {
+        let path = test_path("fallback-bitwarden-name");
+        create_config_with_content(
+            &path,
+            0o600,
+            "backend: age\nage: {path: /keys}\nbws_access_token: token\nsecret_ids:\n  - deploy\n\
+            key_options:\n  deploy:\n    \
+            fingerprint: SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU\n    \
+            fallbacks: [{backend: bitwarden, id: deploy-key}]\n",
+        );
+
+        let error = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a Bitwarden fallback referenced by name should fail");
+
+        assert!(error.to_string().contains("must be the UUID"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn project_ids_replace_secret_ids() {
+        let path = test_path("projects");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_project_ids:\n  - 00000000-0000-0000-0000-0000000000aa\n",
+        );
+
+        let config = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect("project IDs alone should load");
+
+        assert!(config.bw_secret_ids.is_empty());
+        assert_eq!(
+            config.bw_project_ids,
+            vec!["00000000-0000-0000-0000-0000000000aa"]
+        );
+        fs::remove_file(path).expect("failed to remove test config");
+    }
+
+    #[test]
+    fn project_ids_are_checked() {
+        let path = test_path("projects-invalid");
+        create_config_with_content(
+            &path,
+            0o600,
+            "bws_access_token: token\nbw_project_ids:\n  - infrastructure\n",
+        );
+        let invalid = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("a project ID which is not a UUID should fail");
+        create_config_with_content(
+            &path,
+            0o600,
+            "backend: keepass\nkeepass: {database: /keys.kdbx}\nbw_secret_ids:\n  - deploy\n\
+            bw_project_ids:\n  - 00000000-0000-0000-0000-0000000000aa\n",
+        );
+        let unsupported = Config::load(&Some(path.to_string_lossy().into_owned()))
+            .expect_err("project IDs with another backend should fail");
+
+        assert!(invalid
+            .to_string()
+            .contains("Invalid Bitwarden project ID, expected a UUID: infrastructure"));
+        assert!(unsupported
+            .to_string()
+            .contains("bw_project_ids is only supported by the bitwarden backend"));
+        fs::remove_file(path).expect("failed to remove test config");
+    }
 }
@@ -0,0 +1,103 @@
+use crate::backends;
+use crate::bitwarden::daemon::{add_discovered_secrets, build_agent};
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Check configuration, authentication and every configured key, printing a report
+pub async fn run_doctor(config_file: Option<String>) -> Result<()> {
+    let mut config = Config::load(&config_file).context("Failed to load configuration")?;
+    println!("[ok]   Configuration loaded");
+
+    let policy = &config.key_policy;
+    println!(
+        "[info] Key policy: minimum RSA size {} bits, algorithms: {}, SHA-1 signatures: {}",
+        policy.min_rsa_bits,
+        policy
+            .allowed_algorithms
+            .as_ref()
+            .map(|algorithms| algorithms.join(", "))
+            .unwrap_or_else(|| "any".to_string()),
+        if policy.allow_sha1 {
+            "allowed"
+        } else {
+            "refused"
+        }
+    );
+
+    let backend = config.backend.connect(&config).await?;
+    println!(
+        "[ok]   Authenticated to {} ({})",
+        config.backend.endpoint(&config),
+        config.backend.name()
+    );
+    backends::resolve_references(&mut config, &backend).await?;
+    let fetcher = Arc::new(backends::with_fallbacks(&config, backend));
+    add_discovered_secrets(&mut config, fetcher.as_ref()).await;
+
+    let agent = build_agent(&config, fetcher);
+    let checks = agent.check_keys().await;
+    agent.clear_cache();
+
+    let mut failed = 0;
+    for check in &checks {
+        let name = check.name.as_deref().unwrap_or("unknown");
+        match &check.result {
+            Ok(keys) => {
+                for key in keys {
+                    println!(
+                        "[ok]   {} ({}): {} {}",
+                        name,
+                        check.secret_id,
+                        key.algorithm(),
+                        key.fingerprint(ssh_key::HashAlg::Sha256)
+                    );
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("[fail] {} ({}): {}", name, check.secret_id, e);
+            }
+        }
+        // Keys of a bundle refused by the key policy, its other keys are served
+        for rejected in &check.rejected {
+            println!(
+                "[fail] {} ({}): {} {} rejected by key policy: {}",
+                name,
+                check.secret_id,
+                rejected.public_key.algorithm(),
+                rejected.public_key.fingerprint(ssh_key::HashAlg::Sha256),
+                rejected.reason
+            );
+        }
+        if check.result.is_ok() && !check.rejected.is_empty() {
+            failed += 1;
+        }
+    }
+
+    // Keys stored in several secrets are only advertised once
+    let mut sources: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for check in &checks {
+        for key in check.result.iter().flatten() {
+            sources
+                .entry(key.fingerprint(ssh_key::HashAlg::Sha256).to_string())
+                .or_default()
+                .push(check.secret_id.to_string());
+        }
+    }
+    for (fingerprint, secret_ids) in sources.iter().filter(|(_, ids)| ids.len() > 1) {
+        println!(
+            "[info] Key {} is stored in {} secrets ({}), it is advertised once",
+            fingerprint,
+            secret_ids.len(),
+            secret_ids.join(", ")
+        );
+    }
+
+    if failed > 0 {
+        bail!("{} of {} secrets failed the checks", failed, checks.len());
+    }
+    println!("All {} secrets are ready to be served", checks.len());
+    Ok(())
+}
@@ -1,8 +1,10 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::debug;
+use std::ffi::OsStr;
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
 /// Get the PID file path
 fn get_pid_file_path() -> PathBuf {
@@ -12,12 +14,33 @@ fn get_pid_file_path() -> PathBuf {
     PathBuf::from(format!("/tmp/vc-{}-ssh-agent.pid", username))
 }
 
-// Socket setup
-pub fn get_socket_file_path() -> PathBuf {
+/// Path of the list of the profile sockets created by the running agent
+fn get_profile_sockets_file_path() -> PathBuf {
     let username = std::env::var("USER")
         .context("Failed to get username")
         .unwrap();
-    PathBuf::from(format!("/tmp/vc-{}-ssh-agent.sock", username))
+    PathBuf::from(format!("/tmp/vc-{}-ssh-agent.sockets", username))
+}
+
+// Socket setup, each profile gets a socket of its own next to the default one
+pub fn get_socket_file_path(profile: Option<&str>) -> PathBuf {
+    let username = std::env::var("USER")
+        .context("Failed to get username")
+        .unwrap();
+    match profile {
+        Some(profile) => PathBuf::from(format!("/tmp/vc-{}-ssh-agent-{}.sock", username, profile)),
+        None => PathBuf::from(format!("/tmp/vc-{}-ssh-agent.sock", username)),
+    }
+}
+
+/// Directory of the state kept across restarts, e.g. the Bitwarden authentication state
+pub fn get_state_dir() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().context("Unable to determine home directory")?;
+    #[cfg(target_os = "macos")]
+    let state_dir = home_dir.join("Library").join("Application Support");
+    #[cfg(not(target_os = "macos"))]
+    let state_dir = home_dir.join(".local").join("state");
+    Ok(state_dir.join(env!("CARGO_PKG_NAME")))
 }
 
 /// Read the PID from the PID file
@@ -48,6 +71,59 @@ pub fn write_pid(pid: i32) -> Result<()> {
     Ok(())
 }
 
+/// Record the profile sockets of the agent next to its PID file, so that `stop` removes
+/// them whatever the configuration says by then
+pub fn write_profile_sockets(paths: &[PathBuf]) -> Result<()> {
+    write_socket_list(&get_profile_sockets_file_path(), paths)
+}
+
+/// Profile sockets recorded by the running agent, `None` when it recorded none
+pub fn read_profile_sockets() -> Result<Option<Vec<PathBuf>>> {
+    read_socket_list(&get_profile_sockets_file_path())
+}
+
+/// Write one socket path per line
+fn write_socket_list(list_path: &Path, paths: &[PathBuf]) -> Result<()> {
+    let mut content = Vec::new();
+    for path in paths {
+        content.extend_from_slice(path.as_os_str().as_bytes());
+        content.push(b'\n');
+    }
+    fs::write(list_path, content).with_context(|| {
+        format!(
+            "Failed to write profile sockets file at {}",
+            list_path.display()
+        )
+    })?;
+    fs::set_permissions(list_path, std::fs::Permissions::from_mode(0o600))
+        .context("Failed to set profile sockets file permissions")?;
+    debug!("Profile sockets file written: {}", list_path.display());
+    Ok(())
+}
+
+/// Read the socket paths written by `write_socket_list`
+fn read_socket_list(list_path: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let content = match fs::read(list_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to read profile sockets file at {}",
+                    list_path.display()
+                )
+            })
+        }
+    };
+    Ok(Some(
+        content
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+            .collect(),
+    ))
+}
+
 /// Remove PID file
 fn remove_pid_file() -> Result<()> {
     let pid_path = get_pid_file_path();
@@ -57,17 +133,45 @@ fn remove_pid_file() -> Result<()> {
 
 /// Remove socket file
 fn remove_socket_file() -> Result<()> {
-    let socket_path = get_socket_file_path();
-    remove_file(&socket_path, "socket")?;
+    remove_socket(&get_socket_file_path(None))
+}
+
+/// Remove the sockets of the profiles
+pub fn remove_profile_socket_files(paths: &[PathBuf]) -> Result<()> {
+    for path in paths {
+        remove_socket(path)?;
+    }
     Ok(())
 }
 
 pub fn cleanup_files() -> Result<()> {
     remove_pid_file()?;
     remove_socket_file()?;
+    remove_file(&get_profile_sockets_file_path(), "profile sockets")?;
     Ok(())
 }
 
+/// Remove a stale socket, refusing to remove anything else found at `path`,
+/// e.g. a key or a config file set as the socket of a profile by mistake
+pub fn remove_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove socket file at {}", path.display()))?;
+            debug!("socket file removed: {}", path.display());
+            Ok(())
+        }
+        Ok(_) => bail!(
+            "Refusing to remove {}, it is not a socket. Check the socket paths of the configuration",
+            path.display()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to inspect socket file at {}", path.display()))
+        }
+    }
+}
+
 /// Remove file
 pub fn remove_file(path: &PathBuf, what: &str) -> Result<()> {
     if path.exists() {
@@ -80,3 +184,6 @@ pub fn remove_file(path: &PathBuf, what: &str) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(all(test, unix))]
+mod tests;
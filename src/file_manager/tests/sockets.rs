@@ -0,0 +1,67 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::file_manager::{
+        read_socket_list, remove_profile_socket_files, remove_socket, write_socket_list,
+    };
+
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn test_path(name: &str) -> PathBuf {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before Unix epoch")
+            .as_nanos();
+        std::env::temp_dir().join(format!(
+            "vault-conductor-{name}-{}-{timestamp}",
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn stale_socket_is_removed() {
+        let path = test_path("stale.sock");
+        drop(UnixListener::bind(&path).unwrap());
+
+        remove_socket(&path).unwrap();
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn file_at_socket_path_is_kept() {
+        let path = test_path("id_ed25519");
+        fs::write(&path, "private key").unwrap();
+
+        let error = remove_socket(&path).expect_err("a regular file should not be removed");
+        let profiles = remove_profile_socket_files(std::slice::from_ref(&path));
+
+        assert!(error.to_string().contains("it is not a socket"));
+        assert!(profiles.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "private key");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_socket_is_ignored() {
+        assert!(remove_socket(&test_path("missing.sock")).is_ok());
+    }
+
+    #[test]
+    fn recorded_profile_sockets_are_read_back() {
+        let list = test_path("sockets");
+        let paths = vec![test_path("work.sock"), test_path("personal sockets.sock")];
+
+        write_socket_list(&list, &paths).unwrap();
+
+        assert_eq!(read_socket_list(&list).unwrap(), Some(paths));
+        fs::remove_file(list).unwrap();
+    }
+
+    #[test]
+    fn missing_profile_sockets_list_is_none() {
+        assert_eq!(read_socket_list(&test_path("no-sockets")).unwrap(), None);
+    }
+}
@@ -33,8 +33,16 @@ pub fn get_log_file_path() -> PathBuf {
     get_log_dir().join(LOG_FILENAME)
 }
 
-/// Set up logging - to stdout if foreground, to file if background
-pub fn setup_logging(log_level: log::LevelFilter, foreground: bool) -> Result<()> {
+/// Where log records are written
+pub enum LogTarget {
+    Stdout,
+    /// Used when stdout carries the agent protocol
+    Stderr,
+    File,
+}
+
+/// Set up logging - to stdout if foreground, to file if background, to stderr when serving on stdio
+pub fn setup_logging(log_level: log::LevelFilter, target: LogTarget) -> Result<()> {
     let mut builder: Builder = env_logger::Builder::new();
     builder
         .filter_level(log_level)
@@ -42,25 +50,31 @@ pub fn setup_logging(log_level: log::LevelFilter, foreground: bool) -> Result<()
         .format_module_path(true)
         .format_target(false);
 
-    if foreground {
+    match target {
         // Log to stdout in foreground mode
-        builder.target(env_logger::Target::Stdout);
-    } else {
-        // Log to file in background mode
-        let log_dir = get_log_dir();
+        LogTarget::Stdout => {
+            builder.target(env_logger::Target::Stdout);
+        }
+        LogTarget::Stderr => {
+            builder.target(env_logger::Target::Stderr);
+        }
+        LogTarget::File => {
+            // Log to file in background mode
+            let log_dir = get_log_dir();
 
-        // Create log directory if it doesn't exist
-        fs::create_dir_all(&log_dir)?;
+            // Create log directory if it doesn't exist
+            fs::create_dir_all(&log_dir)?;
 
-        let log_file = log_dir.join(LOG_FILENAME);
-        let target = Box::new(
-            fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(log_file)?,
-        );
+            let log_file = log_dir.join(LOG_FILENAME);
+            let target = Box::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log_file)?,
+            );
 
-        builder.target(env_logger::Target::Pipe(target));
+            builder.target(env_logger::Target::Pipe(target));
+        }
     }
 
     builder.init();
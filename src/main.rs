@@ -3,14 +3,21 @@ use clap::{Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use log::{debug, info};
 
+mod backends;
 mod bitwarden;
 mod config;
+mod doctor;
 mod file_manager;
 mod logging;
 mod process_manager;
-use crate::bitwarden::client_wrapper::start_agent_foreground;
-use crate::logging::setup_logging;
-use crate::process_manager::{show_log_file, start_agent_background, stop_agent};
+mod secure_memory;
+use crate::bitwarden::daemon::start_agent_foreground;
+use crate::bitwarden::server::serve_stdio;
+use crate::doctor::run_doctor;
+use crate::logging::{setup_logging, LogTarget};
+use crate::process_manager::{
+    refresh_agent, reset_agent_counters, show_log_file, start_agent_background, stop_agent,
+};
 
 fn long_version() -> &'static str {
     Box::leak(
@@ -37,6 +44,24 @@ struct StartArgs {
     config_file: Option<String>,
 }
 
+#[derive(Parser, Clone)]
+struct ServeStdioArgs {
+    /// Path to the configuration file
+    #[arg(long = "config", required = false)]
+    config_file: Option<String>,
+
+    /// Serve only the secrets of this profile
+    #[arg(long = "profile", required = false)]
+    profile: Option<String>,
+}
+
+#[derive(Parser, Clone)]
+struct ConfigArgs {
+    /// Path to the configuration file
+    #[arg(long = "config", required = false)]
+    config_file: Option<String>,
+}
+
 /// A Rust CLI boilerplate application
 #[derive(Parser)]
 #[command(
@@ -63,9 +88,17 @@ enum Commands {
     /// Start the SSH Agent in the background
     Start(StartArgs),
     /// Stop the background SSH Agent
-    Stop,
+    Stop(ConfigArgs),
+    /// Have the background SSH Agent fetch its keys again, e.g. after rotating one
+    Refresh,
+    /// Give the keys capped by max_signatures a new allowance in the background SSH Agent
+    ResetCounters,
     /// Show logs in the terminal
     Logs,
+    /// Check configuration, authentication and keys, then print a report
+    Doctor(ConfigArgs),
+    /// Serve a single agent session on stdin/stdout, e.g. through socat or docker exec -i
+    ServeStdio(ServeStdioArgs),
 }
 
 #[tokio::main]
@@ -76,8 +109,16 @@ async fn main() -> Result<()> {
     // note: using 'ref' to avoid consuming the args and have to clone it
     let is_child = std::env::var("VC_DAEMON_CHILD").is_ok();
 
-    // Set up logging to stdout if foreground, to file if background
-    setup_logging(cli.verbose.log_level_filter(), !is_child)?;
+    // Set up logging to stdout if foreground, to file if background,
+    // to stderr if stdout carries the agent protocol
+    let log_target = if matches!(cli.command, Commands::ServeStdio(_)) {
+        LogTarget::Stderr
+    } else if is_child {
+        LogTarget::File
+    } else {
+        LogTarget::Stdout
+    };
+    setup_logging(cli.verbose.log_level_filter(), log_target)?;
 
     debug!("*** Debug logging enabled ***");
     info!("Starting application");
@@ -94,12 +135,28 @@ async fn main() -> Result<()> {
                     .context("Failed to start agent in background")?;
             }
         }
-        Commands::Stop => {
-            stop_agent().context("Failed to stop agent")?;
+        Commands::Stop(args) => {
+            stop_agent(args.config_file).context("Failed to stop agent")?;
+        }
+        Commands::Refresh => {
+            refresh_agent().context("Failed to refresh agent")?;
+        }
+        Commands::ResetCounters => {
+            reset_agent_counters().context("Failed to reset signature counters")?;
         }
         Commands::Logs => {
             show_log_file().context("Failed to open log file")?;
         }
+        Commands::Doctor(args) => {
+            run_doctor(args.config_file)
+                .await
+                .context("Doctor found problems")?;
+        }
+        Commands::ServeStdio(args) => {
+            serve_stdio(args.config_file, args.profile)
+                .await
+                .context("Failed to serve the agent on stdin/stdout")?;
+        }
     }
 
     Ok(())
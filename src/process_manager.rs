@@ -1,13 +1,20 @@
-use crate::{config::Config, file_manager::*};
+use crate::{
+    config::{Config, DEFAULT_SHUTDOWN_TIMEOUT},
+    file_manager::*,
+};
 use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::{
     path::PathBuf,
     process::{Child, Command, Stdio},
+    time::{Duration, Instant},
 };
 
 use crate::logging::get_log_file_path;
 
+/// Time given to the agent to exit on top of its `shutdown_timeout`
+const STOP_MARGIN: Duration = Duration::from_secs(2);
+
 /// Check if a process with the given PID is running
 #[cfg(not(windows))]
 fn is_process_running(pid: i32) -> bool {
@@ -23,8 +30,46 @@ fn is_process_running(pid: i32) -> bool {
         .unwrap_or(false)
 }
 
+/// Poll until the process with the given PID exits, returns false if it is still running after `timeout`
+fn wait_for_exit(pid: i32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while is_process_running(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+/// Remove the PID file and the sockets, the ones of the profiles included.
+/// The profile sockets are the ones recorded by the agent, or the configured ones
+/// for an agent that recorded none.
+fn cleanup_agent_files(config: Option<&Config>) -> Result<()> {
+    match read_profile_sockets()? {
+        Some(paths) => remove_profile_socket_files(&paths)?,
+        None => {
+            if let Some(config) = config {
+                remove_profile_socket_files(&config.profile_socket_paths())?;
+            }
+        }
+    }
+    cleanup_files()
+}
+
 /// Stop the agent process
-pub fn stop_agent() -> Result<()> {
+pub fn stop_agent(config_file: Option<String>) -> Result<()> {
+    // Gives the shutdown timeout, and the profile sockets of an agent that did not record them
+    let config = match Config::load(&config_file) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            warn!(
+                "Failed to load configuration, using the default shutdown timeout: {:#}",
+                e
+            );
+            None
+        }
+    };
     match read_pid()? {
         Some(pid) => {
             if is_process_running(pid) {
@@ -38,11 +83,16 @@ pub fn stop_agent() -> Result<()> {
 
                 match result {
                     Ok(status) if status.success() => {
-                        // Wait a bit for graceful shutdown
-                        std::thread::sleep(std::time::Duration::from_millis(500));
+                        // Wait for the agent to drain its requests in flight,
+                        // plus a margin for it to wipe its cache and exit
+                        let timeout = config
+                            .as_ref()
+                            .map(|config| config.get_shutdown_timeout())
+                            .unwrap_or(Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT))
+                            + STOP_MARGIN;
 
                         // Check if it's still running
-                        if is_process_running(pid) {
+                        if !wait_for_exit(pid, timeout) {
                             debug!("Process still running, sending SIGKILL");
                             // Force kill if still running
                             Command::new("kill")
@@ -52,20 +102,20 @@ pub fn stop_agent() -> Result<()> {
                                 .context("Failed to force kill agent process")?;
                         }
 
-                        cleanup_files()?;
+                        cleanup_agent_files(config.as_ref())?;
                         info!("Agent stopped successfully");
                         Ok(())
                     }
                     _ => {
                         // Process might already be dead
-                        cleanup_files()?;
+                        cleanup_agent_files(config.as_ref())?;
                         info!("Agent process not found, cleaned up PID and socket files");
                         Ok(())
                     }
                 }
             } else {
                 debug!("Agent process with PID {} is not running", pid);
-                cleanup_files()?;
+                cleanup_agent_files(config.as_ref())?;
                 info!("Agent was not running, cleaned up stale PID and socket files");
                 Ok(())
             }
@@ -77,6 +127,41 @@ pub fn stop_agent() -> Result<()> {
     }
 }
 
+/// Ask the running agent to fetch its keys again, see `refresh_interval`
+pub fn refresh_agent() -> Result<()> {
+    let pid = signal_agent("-HUP")?;
+    info!("Asked agent with PID {} to refresh its keys", pid);
+    Ok(())
+}
+
+/// Give the keys capped by `max_signatures` a new allowance in the running agent
+pub fn reset_agent_counters() -> Result<()> {
+    let pid = signal_agent("-USR1")?;
+    info!(
+        "Asked agent with PID {} to reset its signature counters",
+        pid
+    );
+    Ok(())
+}
+
+/// Send `signal` to the running agent, returning its PID
+fn signal_agent(signal: &str) -> Result<i32> {
+    match read_pid()? {
+        Some(pid) if is_process_running(pid) => {
+            let status = Command::new("kill")
+                .arg(signal)
+                .arg(pid.to_string())
+                .status()
+                .context("Failed to signal agent process")?;
+            if !status.success() {
+                return Err(anyhow!("Failed to signal agent process with PID: {}", pid));
+            }
+            Ok(pid)
+        }
+        _ => Err(anyhow!("Agent is not running")),
+    }
+}
+
 /// Start the agent in a background process
 pub fn start_agent_background(config_file: Option<String>) -> Result<()> {
     // Check if agent is already running
@@ -88,7 +173,7 @@ pub fn start_agent_background(config_file: Option<String>) -> Result<()> {
             ));
         } else {
             debug!("Cleaning up stale PID file");
-            cleanup_files()?;
+            cleanup_agent_files(None)?;
         }
     }
 
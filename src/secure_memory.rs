@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use log::{debug, warn};
+use sha2::{Digest, Sha512};
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(unix)]
+use std::sync::Mutex;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Size of the random pre-key the shielding key is derived from.
+/// Same size used by OpenSSH: recovering the key requires reading all of it,
+/// which makes side-channel attacks on memory considerably harder.
+const SHIELD_PREKEY_LEN: usize = 16 * 1024;
+
+/// Prevent the agent process from writing core dumps and from being
+/// inspected by other processes of the same user (Linux only).
+#[cfg(unix)]
+pub fn disable_core_dumps() {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: setrlimit only reads the provided, fully initialized struct
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        warn!(
+            "Failed to disable core dumps: {}",
+            std::io::Error::last_os_error()
+        );
+    } else {
+        debug!("Core dumps disabled (RLIMIT_CORE set to 0)");
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: PR_SET_DUMPABLE takes plain integer arguments
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+            warn!(
+                "Failed to mark process as non-dumpable: {}",
+                std::io::Error::last_os_error()
+            );
+        } else {
+            debug!("Process marked as non-dumpable (PR_SET_DUMPABLE=0)");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn disable_core_dumps() {}
+
+/// Fixed-size heap buffer for secret material.
+///
+/// The pages holding the buffer are locked in RAM (best effort) so they are
+/// never written to swap, and the content is wiped when the buffer is dropped.
+pub struct SecretBuffer {
+    bytes: Box<[u8]>,
+    locked: bool,
+}
+
+impl SecretBuffer {
+    /// Copy `data` into a new locked buffer
+    pub fn new(data: &[u8]) -> Self {
+        let bytes: Box<[u8]> = data.into();
+        let locked = lock_memory(&bytes);
+        Self { bytes, locked }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Zeroize for SecretBuffer {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        self.zeroize();
+        if self.locked {
+            unlock_memory(&self.bytes);
+        }
+    }
+}
+
+impl ZeroizeOnDrop for SecretBuffer {}
+
+impl fmt::Debug for SecretBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBuffer([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+/// Per-process key used to keep cached secrets encrypted while at rest in
+/// memory ("shielding", the approach of OpenSSH's ssh-agent).
+///
+/// Only the random pre-key is kept around, the actual cipher key is derived
+/// from it for the duration of each shield/unshield operation.
+pub struct KeyShield {
+    prekey: SecretBuffer,
+}
+
+impl KeyShield {
+    pub fn new() -> Self {
+        let mut prekey = Zeroizing::new(vec![0u8; SHIELD_PREKEY_LEN]);
+        OsRng.fill_bytes(&mut prekey);
+        Self {
+            prekey: SecretBuffer::new(&prekey),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        let digest: Zeroizing<[u8; 64]> =
+            Zeroizing::new(Sha512::digest(self.prekey.as_bytes()).into());
+        ChaCha20Poly1305::new(Key::from_slice(&digest[..32]))
+    }
+
+    /// Encrypt `plaintext` with the process key
+    pub fn shield(&self, plaintext: &[u8]) -> Result<ShieldedSecret> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Failed to shield secret in memory"))?;
+        Ok(ShieldedSecret {
+            nonce: nonce.into(),
+            ciphertext,
+        })
+    }
+
+    /// Decrypt a shielded secret into locked memory, wiped when dropped
+    pub fn unshield(&self, shielded: &ShieldedSecret) -> Result<SecretBuffer> {
+        let plaintext = Zeroizing::new(
+            self.cipher()
+                .decrypt(
+                    Nonce::from_slice(&shielded.nonce),
+                    shielded.ciphertext.as_ref(),
+                )
+                .map_err(|_| anyhow!("Failed to unshield secret, memory may be corrupted"))?,
+        );
+        Ok(SecretBuffer::new(&plaintext))
+    }
+}
+
+impl Default for KeyShield {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Secret encrypted with a [`KeyShield`]
+pub struct ShieldedSecret {
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+impl fmt::Debug for ShieldedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ShieldedSecret([ENCRYPTED; {}])", self.ciphertext.len())
+    }
+}
+
+/// Number of locked buffers on each page, by page address. `munlock` is not
+/// reference-counted, a page is only unlocked once no locked buffer uses it.
+#[cfg(unix)]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// Addresses of the pages backing `bytes`
+#[cfg(unix)]
+fn pages(bytes: &[u8]) -> impl Iterator<Item = usize> {
+    // SAFETY: sysconf only reads a system setting
+    let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    };
+    let start = bytes.as_ptr() as usize;
+    let first = start - start % page_size;
+    (first..start + bytes.len()).step_by(page_size)
+}
+
+/// Lock the pages backing `bytes` in RAM. Returns whether locking succeeded.
+#[cfg(unix)]
+fn lock_memory(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    // Held while locking, so that a page is not unlocked by another buffer meanwhile
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap();
+    // SAFETY: the pointer and length describe a live allocation owned by the caller
+    if unsafe { libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.len()) } != 0 {
+        // Usually caused by a low RLIMIT_MEMLOCK, not worth failing for
+        debug!(
+            "Failed to lock {} bytes of secret memory: {}",
+            bytes.len(),
+            std::io::Error::last_os_error()
+        );
+        return false;
+    }
+    for page in pages(bytes) {
+        *locked_pages.entry(page).or_default() += 1;
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn lock_memory(_bytes: &[u8]) -> bool {
+    false
+}
+
+/// Unlock the pages backing `bytes` that no other locked buffer uses
+#[cfg(unix)]
+fn unlock_memory(bytes: &[u8]) {
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap();
+    for page in pages(bytes) {
+        let Some(count) = locked_pages.get_mut(&page) else {
+            continue;
+        };
+        *count -= 1;
+        if *count == 0 {
+            locked_pages.remove(&page);
+            // SAFETY: the page is mapped, it holds part of a live allocation owned by the caller
+            unsafe {
+                libc::munlock(page as *const libc::c_void, 1);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn unlock_memory(_bytes: &[u8]) {}
+
+#[cfg(all(test, unix))]
+mod tests;
@@ -0,0 +1,22 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::secure_memory::{pages, SecretBuffer, LOCKED_PAGES};
+
+    #[test]
+    fn pages_of_live_buffers_stay_locked() {
+        let first = SecretBuffer::new(&[1; 64]);
+        let second = SecretBuffer::new(&[2; 64]);
+        if !second.locked {
+            // RLIMIT_MEMLOCK too low to lock anything
+            return;
+        }
+        let second_pages: Vec<usize> = pages(second.as_bytes()).collect();
+
+        drop(first);
+
+        let locked_pages = LOCKED_PAGES.lock().unwrap();
+        assert!(second_pages
+            .iter()
+            .all(|page| locked_pages.contains_key(page)));
+    }
+}
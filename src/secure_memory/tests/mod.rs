@@ -0,0 +1,3 @@
+pub mod locking;
+pub mod shield;
+pub mod zeroize;
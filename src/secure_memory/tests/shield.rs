@@ -0,0 +1,36 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::secure_memory::KeyShield;
+
+    #[test]
+    fn shielded_secret_round_trips() {
+        let shield = KeyShield::new();
+
+        let shielded = shield.shield(b"private key material").unwrap();
+        let unshielded = shield.unshield(&shielded).unwrap();
+
+        assert_eq!(unshielded.as_bytes(), b"private key material");
+    }
+
+    #[test]
+    fn shielded_secret_does_not_contain_plaintext() {
+        let shield = KeyShield::new();
+        let plaintext = b"private key material";
+
+        let shielded = shield.shield(plaintext).unwrap();
+
+        assert!(!format!("{shielded:?}").contains("private"));
+        assert!(shielded.ciphertext.len() >= plaintext.len());
+        assert!(!shielded
+            .ciphertext
+            .windows(plaintext.len())
+            .any(|window| window == plaintext));
+    }
+
+    #[test]
+    fn other_processes_keys_cannot_unshield() {
+        let shielded = KeyShield::new().shield(b"private key material").unwrap();
+
+        assert!(KeyShield::new().unshield(&shielded).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::secure_memory::SecretBuffer;
+
+    use std::mem::ManuallyDrop;
+    use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn secret_types_zeroize_on_drop() {
+        assert_zeroize_on_drop::<SecretBuffer>();
+        assert_zeroize_on_drop::<Zeroizing<String>>();
+        assert_zeroize_on_drop::<Zeroizing<Vec<u8>>>();
+    }
+
+    #[test]
+    fn secret_buffer_keeps_a_copy_of_the_data() {
+        let buffer = SecretBuffer::new(b"private key material");
+
+        assert_eq!(buffer.as_bytes(), b"private key material");
+        assert!(!format!("{buffer:?}").contains("private"));
+    }
+
+    #[test]
+    fn secret_buffer_is_wiped_by_zeroize() {
+        let mut buffer = SecretBuffer::new(b"private key material");
+
+        buffer.zeroize();
+
+        assert!(buffer.as_bytes().iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn zeroizing_wipes_memory_on_drop() {
+        let mut secret = ManuallyDrop::new(Zeroizing::new([0x42u8; 32]));
+
+        // SAFETY: the value is dropped exactly once and only its inline,
+        // plain-byte storage is read afterwards
+        unsafe { ManuallyDrop::drop(&mut secret) };
+
+        assert!(secret.iter().all(|byte| *byte == 0));
+    }
+}